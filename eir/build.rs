@@ -1,22 +1,33 @@
-//! This build script copies the `memory.x` file from the crate root into
-//! a directory where the linker can always find it at build time.
-//! For many projects this is optional, as the linker always searches the
-//! project root directory -- wherever `Cargo.toml` is. However, if you
-//! are using a workspace or have a more complicated build setup, this
-//! build script becomes required. Additionally, by requesting that
-//! Cargo re-run the build script whenever `memory.x` is changed,
-//! updating `memory.x` ensures a rebuild of the application with the
-//! new memory settings.
+//! This build script does two things:
+//!
+//! * For the microcontroller, it copies the `memory.x` file from the crate root into a directory
+//!   where the linker can always find it, and asks Cargo to re-run whenever `memory.x` changes, so
+//!   that updating it rebuilds the application with the new memory settings.
+//! * For every target, it scans the bindings generated by `microros-sys` and writes
+//!   `messages.rs` to `OUT_DIR`, which `msg` includes. It holds the message and service wrappers,
+//!   the owned message structs and the plain messages, grouped into a module per package. See
+//!   `generate_messages`.
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
-use std::fs::File;
+use std::fs::{self, File};
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 fn main() {
+    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
+
+    // the linker scripts only apply to the microcontroller, not to the host build
+    if env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("none") {
+        link_memory_layout(out);
+    }
+
+    generate_messages(out);
+}
+
+fn link_memory_layout(out: &Path) {
     // Put `memory.x` in our output directory and ensure it's
     // on the linker search path.
-    let out = &PathBuf::from(env::var_os("OUT_DIR").unwrap());
     File::create(out.join("memory.x"))
         .unwrap()
         .write_all(include_bytes!("memory.x"))
@@ -34,3 +45,442 @@ fn main() {
     println!("cargo:rustc-link-arg-bins=-Tlink-rp.x");
     println!("cargo:rustc-link-arg-bins=-Tdefmt.x");
 }
+
+const MSG_TYPE_SUPPORT: &str = "rosidl_typesupport_c__get_message_type_support_handle__";
+const SRV_TYPE_SUPPORT: &str = "rosidl_typesupport_c__get_service_type_support_handle__";
+
+/// Scans the bindings generated by `microros-sys` and emits a `generate_msg_wrapper!` for every
+/// message providing `__create`, `__destroy`, `__copy` and a type support handle, plus a
+/// `generate_srv_wrapper!` for every service whose request and response got wrapped.
+/// Every message structure the bindings define also gets an owned counterpart in `owned`, and
+/// the ones without pointers a `generate_plain_message!` in `plain`.
+/// The wrappers are grouped into a module per package.
+fn generate_messages(out: &Path) {
+    // exported by the build script of `microros-sys` through its `links` key
+    let bindings_path = env::var("DEP_MICROROS_BINDINGS")
+        .expect("microros-sys did not export the path to its bindings");
+    println!("cargo:rerun-if-changed={}", bindings_path);
+    let bindings = fs::read_to_string(&bindings_path).expect("failed to read bindings");
+
+    let functions: BTreeSet<&str> = bindings
+        .split("pub fn ")
+        .skip(1)
+        .filter_map(|declaration| declaration.split('(').next())
+        .collect();
+
+    // package name -> (wrapper name, C type name)
+    let mut messages: BTreeMap<&str, Vec<(String, &str)>> = BTreeMap::new();
+    let mut services: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+
+    for function in &functions {
+        if let Some(c_type) = function.strip_suffix("__create") {
+            let complete = functions.contains(format!("{}__destroy", c_type).as_str())
+                && functions.contains(format!("{}__copy", c_type).as_str())
+                && functions.contains(format!("{}{}", MSG_TYPE_SUPPORT, c_type).as_str());
+            let Some((package, name)) = split_type_name(c_type) else {
+                continue;
+            };
+            if complete {
+                messages
+                    .entry(package)
+                    .or_default()
+                    .push((name.replace('_', ""), c_type));
+            }
+        } else if let Some(c_type) = function.strip_prefix(SRV_TYPE_SUPPORT) {
+            let Some((package, name)) = split_type_name(c_type) else {
+                continue;
+            };
+            services.entry(package).or_default().push(name);
+        }
+    }
+
+    let structs = parse_structs(&bindings);
+    let owned = generate_owned_messages(&structs, &functions);
+    let plain = plain_messages(&structs, &functions);
+
+    let packages: BTreeSet<&str> = messages
+        .keys()
+        .chain(owned.keys())
+        .chain(plain.keys())
+        .copied()
+        .collect();
+    let mut generated = String::new();
+    for package in packages {
+        let wrappers = messages.get(package).map(Vec::as_slice).unwrap_or_default();
+        let owned = owned.get(package).map(Vec::as_slice).unwrap_or_default();
+
+        generated += &format!("pub mod {} {{\n", package);
+        for (wrapper, c_type) in wrappers {
+            generated += &format!(
+                "    generate_msg_wrapper!(\n        {wrapper},\n        microros_sys::{c_type},\n        microros_sys::{c_type}__create,\n        microros_sys::{c_type}__destroy,\n        microros_sys::{c_type}__copy,\n        microros_sys::{MSG_TYPE_SUPPORT}{c_type}\n    );\n",
+            );
+        }
+        for service in services.get(package).into_iter().flatten() {
+            let request = format!("{}Request", service);
+            let response = format!("{}Response", service);
+            if !wrappers.iter().any(|(wrapper, _)| *wrapper == request)
+                || !wrappers.iter().any(|(wrapper, _)| *wrapper == response)
+            {
+                continue;
+            }
+            generated += &format!(
+                "    generate_srv_wrapper!(\n        {service},\n        {request},\n        {response},\n        microros_sys::{SRV_TYPE_SUPPORT}{package}__srv__{service}\n    );\n",
+            );
+        }
+        for (name, _) in owned {
+            if wrappers.iter().any(|(wrapper, _)| wrapper == name) {
+                generated += &format!("    generate_owned_conversions!({name});\n");
+            }
+        }
+
+        generated += "\n    pub mod plain {\n";
+        for (name, c_type) in plain.get(package).into_iter().flatten() {
+            generated += &format!(
+                "        generate_plain_message!(\n            {name},\n            microros_sys::{c_type},\n            microros_sys::{MSG_TYPE_SUPPORT}{c_type}\n        );\n",
+            );
+        }
+        generated += "    }\n";
+
+        generated += "\n    pub mod owned {\n";
+        for (_, code) in owned {
+            generated += code;
+        }
+        generated += "    }\n}\n\n";
+    }
+
+    fs::write(out.join("messages.rs"), generated).expect("failed to write message wrappers");
+}
+
+/// Splits e.g. `sensor_msgs__msg__BatteryState` into `sensor_msgs` and `BatteryState`
+fn split_type_name(c_type: &str) -> Option<(&str, &str)> {
+    let (package, name) = c_type
+        .split_once("__msg__")
+        .or_else(|| c_type.split_once("__srv__"))?;
+    // skips helpers like `__Sequence__create`, which are not messages on their own
+    if name.contains("__") {
+        return None;
+    }
+    Some((package, name))
+}
+
+const STRING: &str = "rosidl_runtime_c__String";
+const STRING_TYPE: &str = "heapless::String<{ crate::msg::STRING_CAPACITY }>";
+
+/// How a field of a C message maps onto its owned counterpart
+enum Field<'a> {
+    /// Primitives and arrays of primitives, copied as they are
+    Copy,
+    String,
+    PrimitiveSequence(&'a str),
+    StringSequence,
+    Message(&'a str),
+    MessageSequence(&'a str),
+}
+
+/// Emits an owned struct implementing `OwnedMessage` for every message structure in the bindings
+/// whose fields can all be represented. Returns the structs by package as (name, code) pairs.
+fn generate_owned_messages<'a>(
+    structs: &BTreeMap<&'a str, Vec<(&'a str, &'a str)>>,
+    functions: &BTreeSet<&str>,
+) -> BTreeMap<&'a str, Vec<(String, String)>> {
+    // drops messages with unsupported fields until only representable ones remain,
+    // including all the messages they nest
+    let mut supported: BTreeSet<&str> = structs
+        .keys()
+        .copied()
+        .filter(|name| split_type_name(name).is_some())
+        .collect();
+    loop {
+        let unsupported: Vec<&str> = supported
+            .iter()
+            .copied()
+            .filter(|name| {
+                structs[name]
+                    .iter()
+                    .any(|(_, ty)| classify_field(ty, structs, functions, &supported).is_none())
+            })
+            .collect();
+        if unsupported.is_empty() {
+            break;
+        }
+        for name in unsupported {
+            supported.remove(name);
+        }
+    }
+
+    let mut owned: BTreeMap<&str, Vec<(String, String)>> = BTreeMap::new();
+    for c_type in &supported {
+        let (package, name) = split_type_name(c_type).unwrap();
+        let name = name.replace('_', "");
+        let fields: Vec<(&str, &str, Field)> = structs[c_type]
+            .iter()
+            // placeholder rosidl adds to messages without any fields
+            .filter(|(field, _)| *field != "structure_needs_at_least_one_member")
+            .map(|&(field, ty)| {
+                let kind = classify_field(ty, structs, functions, &supported).unwrap();
+                (field, ty, kind)
+            })
+            .collect();
+
+        let mut declaration = String::new();
+        let mut default = String::new();
+        let mut from_raw = String::new();
+        let mut write_raw = String::new();
+        for (field, ty, kind) in &fields {
+            let owned_type = match kind {
+                Field::Copy => ty.to_string(),
+                Field::String => STRING_TYPE.to_string(),
+                Field::PrimitiveSequence(element) => {
+                    format!("heapless::Vec<{element}, {{ crate::msg::SEQUENCE_CAPACITY }}>")
+                }
+                Field::StringSequence => {
+                    format!("heapless::Vec<{STRING_TYPE}, {{ crate::msg::SEQUENCE_CAPACITY }}>")
+                }
+                Field::Message(c_type) => owned_path(c_type),
+                Field::MessageSequence(c_type) => format!(
+                    "heapless::Vec<{}, {{ crate::msg::SEQUENCE_CAPACITY }}>",
+                    owned_path(c_type)
+                ),
+            };
+            declaration += &format!("            pub {field}: {owned_type},\n");
+
+            default += &if ty.starts_with('[') {
+                format!(
+                    "                    {field}: core::array::from_fn(|_| Default::default()),\n"
+                )
+            } else {
+                format!("                    {field}: Default::default(),\n")
+            };
+
+            let elements =
+                format!("unsafe {{ convert::slice(raw.{field}.data, raw.{field}.size) }}");
+            from_raw += &match kind {
+                Field::Copy => format!("                    {field}: raw.{field},\n"),
+                Field::String => {
+                    format!("                    {field}: convert::string_from_raw(&raw.{field})?,\n")
+                }
+                Field::PrimitiveSequence(_) => format!(
+                    "                    {field}: heapless::Vec::from_slice({elements})\n                        .map_err(|_| ConversionError::CapacityExceeded)?,\n"
+                ),
+                Field::StringSequence => format!(
+                    "                    {field}: convert::collect({elements}.iter().map(convert::string_from_raw))?,\n"
+                ),
+                Field::Message(_) => {
+                    format!("                    {field}: OwnedMessage::from_raw(&raw.{field})?,\n")
+                }
+                Field::MessageSequence(_) => format!(
+                    "                    {field}: convert::collect({elements}.iter().map(OwnedMessage::from_raw))?,\n"
+                ),
+            };
+
+            let resize = |sequence: &str| {
+                format!(
+                    "                if raw.{field}.size != self.{field}.len() {{\n                    unsafe {{\n                        convert::resize_sequence(\n                            &mut raw.{field},\n                            self.{field}.len(),\n                            microros_sys::{sequence}__fini,\n                            microros_sys::{sequence}__init,\n                        )?;\n                    }}\n                }}\n                let elements = unsafe {{ convert::slice_mut(raw.{field}.data, raw.{field}.size) }};\n"
+                )
+            };
+            write_raw += &match kind {
+                Field::Copy => format!("                raw.{field} = self.{field};\n"),
+                Field::String => {
+                    format!("                convert::string_to_raw(&self.{field}, &mut raw.{field})?;\n")
+                }
+                Field::PrimitiveSequence(_) => format!(
+                    "{}                elements.copy_from_slice(&self.{field});\n",
+                    resize(ty)
+                ),
+                Field::StringSequence => format!(
+                    "{}                for (value, element) in self.{field}.iter().zip(elements) {{\n                    convert::string_to_raw(value, element)?;\n                }}\n",
+                    resize(ty)
+                ),
+                Field::Message(_) => {
+                    format!("                self.{field}.write_raw(&mut raw.{field})?;\n")
+                }
+                Field::MessageSequence(_) => format!(
+                    "{}                for (value, element) in self.{field}.iter().zip(elements) {{\n                    value.write_raw(element)?;\n                }}\n",
+                    resize(ty)
+                ),
+            };
+        }
+
+        // `Default` is only implemented for arrays of up to 32 elements, e.g. not for covariances
+        let derivable = fields.iter().all(|(_, ty, _)| array_length(ty) <= 32);
+        let (derives, default) = if derivable {
+            ("Debug, Clone, Default, PartialEq", String::new())
+        } else {
+            (
+                "Debug, Clone, PartialEq",
+                format!(
+                    "        impl Default for {name} {{\n            fn default() -> Self {{\n                Self {{\n{default}                }}\n            }}\n        }}\n\n"
+                ),
+            )
+        };
+
+        let code = format!(
+            "        #[derive({derives})]
+        pub struct {name} {{
+{declaration}        }}
+
+{default}        #[allow(unused_imports, unused_variables)]
+        impl crate::msg::OwnedMessage for {name} {{
+            type Raw = microros_sys::{c_type};
+
+            fn from_raw(raw: &Self::Raw) -> Result<Self, crate::msg::ConversionError> {{
+                use crate::msg::{{convert, ConversionError, OwnedMessage}};
+                Ok(Self {{
+{from_raw}                }})
+            }}
+
+            fn write_raw(&self, raw: &mut Self::Raw) -> Result<(), crate::msg::ConversionError> {{
+                use crate::msg::{{convert, OwnedMessage}};
+{write_raw}                Ok(())
+            }}
+        }}
+
+"
+        );
+        owned.entry(package).or_default().push((name, code));
+    }
+
+    owned
+}
+
+/// Message structures made of primitives, arrays of primitives and other such messages only,
+/// which therefore hold no pointers. Returns (name, C type name) pairs by package.
+fn plain_messages<'a>(
+    structs: &BTreeMap<&'a str, Vec<(&'a str, &'a str)>>,
+    functions: &BTreeSet<&str>,
+) -> BTreeMap<&'a str, Vec<(String, &'a str)>> {
+    let mut plain: BTreeSet<&str> = structs
+        .keys()
+        .copied()
+        .filter(|name| split_type_name(name).is_some())
+        .filter(|name| functions.contains(format!("{MSG_TYPE_SUPPORT}{name}").as_str()))
+        .collect();
+    // drops messages nesting anything else until only plain ones remain
+    loop {
+        let pointers: Vec<&str> = plain
+            .iter()
+            .copied()
+            .filter(|name| {
+                structs[name].iter().any(|(_, ty)| {
+                    !matches!(
+                        classify_field(ty, structs, functions, &plain),
+                        Some(Field::Copy | Field::Message(_))
+                    )
+                })
+            })
+            .collect();
+        if pointers.is_empty() {
+            break;
+        }
+        for name in pointers {
+            plain.remove(name);
+        }
+    }
+
+    let mut messages: BTreeMap<&str, Vec<(String, &str)>> = BTreeMap::new();
+    for c_type in plain {
+        let (package, name) = split_type_name(c_type).unwrap();
+        messages
+            .entry(package)
+            .or_default()
+            .push((name.replace('_', ""), c_type));
+    }
+    messages
+}
+
+fn classify_field<'a>(
+    ty: &'a str,
+    structs: &BTreeMap<&'a str, Vec<(&'a str, &'a str)>>,
+    functions: &BTreeSet<&str>,
+    messages: &BTreeSet<&str>,
+) -> Option<Field<'a>> {
+    if is_primitive(ty) {
+        return Some(Field::Copy);
+    }
+    if let Some(array) = ty.strip_prefix('[') {
+        let (element, _) = array.split_once(';')?;
+        return is_primitive(element).then_some(Field::Copy);
+    }
+    if ty == STRING {
+        return Some(Field::String);
+    }
+    if messages.contains(ty) {
+        return Some(Field::Message(ty));
+    }
+
+    // sequences are structs with `data`, `size` and `capacity`, resized through `__init`/`__fini`
+    let resizable = functions.contains(format!("{ty}__init").as_str())
+        && functions.contains(format!("{ty}__fini").as_str());
+    if !ty.ends_with("__Sequence") || !resizable {
+        return None;
+    }
+    let element = structs
+        .get(ty)?
+        .iter()
+        .find(|(field, _)| *field == "data")?
+        .1
+        .strip_prefix("*mut ")?;
+    if is_primitive(element) {
+        Some(Field::PrimitiveSequence(element))
+    } else if element == STRING {
+        Some(Field::StringSequence)
+    } else if messages.contains(element) {
+        Some(Field::MessageSequence(element))
+    } else {
+        None
+    }
+}
+
+fn is_primitive(ty: &str) -> bool {
+    matches!(
+        ty,
+        "bool" | "u8" | "i8" | "u16" | "i16" | "u32" | "i32" | "u64" | "i64" | "f32" | "f64"
+    ) || ty.starts_with("::core::ffi::c_")
+}
+
+/// Number of elements of a field like `[f64; 36usize]`, zero for anything else
+fn array_length(ty: &str) -> usize {
+    ty.strip_prefix('[')
+        .and_then(|array| array.split_once("; "))
+        .and_then(|(_, length)| length.strip_suffix("usize]"))
+        .and_then(|length| length.parse().ok())
+        .unwrap_or(0)
+}
+
+/// Path of the owned struct generated for e.g. `std_msgs__msg__Header`
+fn owned_path(c_type: &str) -> String {
+    let (package, name) = split_type_name(c_type).unwrap();
+    format!("crate::msg::{}::owned::{}", package, name.replace('_', ""))
+}
+
+/// Collects the fields of all the structs in the bindings, as (name, type) pairs
+fn parse_structs(bindings: &str) -> BTreeMap<&str, Vec<(&str, &str)>> {
+    let mut structs = BTreeMap::new();
+    let mut lines = bindings.lines();
+    while let Some(line) = lines.next() {
+        let Some(name) = line
+            .strip_prefix("pub struct ")
+            .and_then(|declaration| declaration.strip_suffix(" {"))
+        else {
+            continue;
+        };
+
+        let mut fields = Vec::new();
+        for line in lines.by_ref() {
+            let line = line.trim();
+            if line == "}" {
+                break;
+            }
+            // skips the `#[doc = ...]` attributes bindgen emits for commented fields
+            if let Some(field) = line
+                .strip_prefix("pub ")
+                .and_then(|field| field.strip_suffix(','))
+                .and_then(|field| field.split_once(": "))
+            {
+                fields.push(field);
+            }
+        }
+        structs.insert(name, fields);
+    }
+    structs
+}
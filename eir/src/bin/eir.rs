@@ -6,17 +6,19 @@ use core::cell::RefCell;
 
 use defmt::*;
 use eir::microros;
+use eir::microros::AgentState;
+use eir::microros::AgentSupervisor;
 use eir::microros::Allocator;
+use eir::microros::Connection;
 use eir::microros::RclNode;
 use eir::microros::RclcExecutor;
-use eir::microros::RclcSupport;
 use eir::microros::TypedPublisher;
-use eir::msg::BatteryState;
-use eir::msg::Empty;
+use eir::msg::sensor_msgs::BatteryState;
+use eir::msg::std_msgs::Empty;
 use eir::smartled::Ws2812;
 use embassy_executor::InterruptExecutor;
 use embassy_executor::Spawner;
-use embassy_futures::yield_now;
+use embassy_futures::select::select3;
 use embassy_rp::adc::Adc;
 use embassy_rp::bind_interrupts;
 use embassy_rp::gpio;
@@ -54,10 +56,10 @@ impl<T> TimestampedValue<T>
 where
     T: Copy,
 {
-    pub fn new(value: T) -> Self {
+    pub const fn new(value: T) -> Self {
         Self {
             value,
-            timestamp: Instant::now(),
+            timestamp: Instant::from_ticks(0),
         }
     }
 
@@ -77,6 +79,12 @@ struct State {
 
 type SharedState = CriticalSectionMutex<RefCell<State>>;
 
+static STATE: SharedState = CriticalSectionMutex::new(RefCell::new(State {
+    battery_voltage: TimestampedValue::new(0.0),
+}));
+
+static SUPERVISOR: AgentSupervisor = AgentSupervisor::new(microros::DEFAULT_PING_PERIOD);
+
 #[embassy_executor::task]
 async fn run_embassy(p: Peripherals, state: &'static SharedState) {
     defmt::info!("hello");
@@ -104,12 +112,17 @@ async fn run_embassy(p: Peripherals, state: &'static SharedState) {
         state
     )));
 
+    // blinks fast until the agent is connected
     let mut led = Output::new(p.PIN_20, Level::Low);
     loop {
+        let period = match SUPERVISOR.state() {
+            AgentState::AgentConnected => 300,
+            _ => 100,
+        };
         led.set_high();
-        Timer::after_millis(300).await;
+        Timer::after_millis(period).await;
         led.set_low();
-        Timer::after_millis(300).await;
+        Timer::after_millis(period).await;
     }
 }
 
@@ -162,62 +175,62 @@ unsafe fn SWI_IRQ_0() {
 }
 
 #[embassy_executor::main]
-async fn main(spawner: Spawner) {
+async fn main(_spawner: Spawner) {
     let p = embassy_rp::init(Default::default());
-    let state = make_static!(CriticalSectionMutex::new(RefCell::new(State {
-        battery_voltage: TimestampedValue::new(0.0)
-    })));
 
     interrupt::SWI_IRQ_0.set_priority(Priority::P3);
     let embassy_spawner = EXECUTOR_EMBASSY.start(interrupt::SWI_IRQ_0);
-    unwrap!(embassy_spawner.spawn(run_embassy(p, state)));
+    unwrap!(embassy_spawner.spawn(run_embassy(p, &STATE)));
 
     Timer::after_secs(1).await;
 
-    eir::transport::init_rmw_transport();
-
-    let mut allocator = Allocator::default();
+    eir::transport::init_rmw_transport(make_static!(eir::transport::UsbTransport::new()));
 
-    microros::wait_for_agent();
+    let allocator = Allocator::default();
 
-    let mut support = RclcSupport::new(&mut allocator);
-    let mut node = RclNode::new("hati_eir_node", "hati", &mut support);
-    let battery_publisher = TypedPublisher::new(&mut node, "battery");
-    defmt::unwrap!(spawner.spawn(battery_publisher_task(battery_publisher, state)));
-
-    let shutdown_publisher = TypedPublisher::<Empty>::new(&mut node, "cmd_shutdown");
+    // reconnects whenever the host reboots, the session only returns when the agent is lost
+    SUPERVISOR.run(&allocator, session).await;
+}
 
-    defmt::unwrap!(spawner.spawn(shutdown_publisher_task(shutdown_publisher)));
+async fn session(connection: &Connection<'_>) -> Result<(), microros::Error> {
+    let node = RclNode::new("hati_eir_node", "hati", connection.support())?;
+    let mut battery_publisher = TypedPublisher::best_effort(&node, "battery")?;
+    let mut shutdown_publisher = TypedPublisher::<Empty>::new(&node, "cmd_shutdown")?;
 
-    let mut executor = RclcExecutor::new(&mut support, 10, &mut allocator);
+    let mut executor = RclcExecutor::new(connection.support(), 10, connection.allocator())?;
 
-    loop {
-        yield_now().await;
-        executor.spin();
-    }
+    select3(
+        connection.spin(&mut executor),
+        publish_battery(&mut battery_publisher, &STATE),
+        publish_shutdown(&mut shutdown_publisher),
+    )
+    .await;
+    Ok(())
 }
 
 static SHUTDOWN_CHANNEL: Channel<CriticalSectionRawMutex, (), 1> = Channel::new();
 
-#[embassy_executor::task]
-async fn shutdown_publisher_task(mut publisher: TypedPublisher<Empty>) {
+async fn publish_shutdown(publisher: &mut TypedPublisher<'_, Empty>) {
     let message = Empty::default();
     let receiver = SHUTDOWN_CHANNEL.receiver();
     loop {
         receiver.receive().await;
-        publisher.publish(&message);
+        if let Err(e) = publisher.publish(&message) {
+            defmt::warn!("publishing shutdown failed: {}", e);
+        }
     }
 }
 
-#[embassy_executor::task]
-async fn battery_publisher_task(
-    mut publisher: TypedPublisher<BatteryState>,
+async fn publish_battery(
+    publisher: &mut TypedPublisher<'_, BatteryState>,
     state: &'static SharedState,
 ) {
     let mut message = BatteryState::default();
     loop {
         Timer::after_millis(1000).await;
-        publisher.publish(&message);
+        if let Err(e) = publisher.publish(&message) {
+            defmt::warn!("publishing battery state failed: {}", e);
+        }
         let (voltage, timestamp) = state.lock(|c| c.borrow().battery_voltage.get());
         message.voltage = voltage;
         message.header.stamp = timestamp.stamp();
@@ -7,20 +7,21 @@ use eir::microros;
 use eir::microros::Allocator;
 use eir::microros::RclNode;
 use eir::microros::RclPublisher;
+use eir::microros::RclTimer;
 use eir::microros::RclcExecutor;
 use eir::microros::RclcSupport;
 use embassy_executor::InterruptExecutor;
 use embassy_executor::Spawner;
-use embassy_futures::yield_now;
 use embassy_rp::gpio;
 use embassy_rp::interrupt;
 use embassy_rp::interrupt::InterruptExt as _;
 use embassy_rp::interrupt::Priority;
 use embassy_rp::Peripherals;
-use embassy_time::Timer;
+use embassy_time::{Duration, Timer};
 use gpio::{Level, Output};
 use microros_sys::rosidl_typesupport_c__get_message_type_support_handle__std_msgs__msg__Int32;
 use microros_sys::std_msgs__msg__Int32__create;
+use static_cell::make_static;
 use {defmt_rtt as _, panic_probe as _};
 
 #[embassy_executor::task]
@@ -47,7 +48,7 @@ unsafe fn SWI_IRQ_0() {
 }
 
 #[embassy_executor::main]
-async fn main(spawner: Spawner) {
+async fn main(_spawner: Spawner) {
     let p = embassy_rp::init(Default::default());
 
     interrupt::SWI_IRQ_0.set_priority(Priority::P3);
@@ -56,35 +57,29 @@ async fn main(spawner: Spawner) {
 
     Timer::after_secs(1).await;
 
-    eir::transport::init_rmw_transport();
+    eir::transport::init_rmw_transport(make_static!(eir::transport::UsbTransport::new()));
 
-    let mut allocator = Allocator::default();
+    let allocator = make_static!(Allocator::default());
 
-    microros::wait_for_agent();
+    unwrap!(microros::wait_for_agent(microros::PingPolicy::default()).await);
 
-    let mut support = RclcSupport::new(&mut allocator);
-    let mut node = RclNode::new("pico_node", "", &mut support);
-    let publisher = RclPublisher::new(
-        &mut node,
+    let support = make_static!(defmt::unwrap!(RclcSupport::new(allocator)));
+    let node = make_static!(defmt::unwrap!(RclNode::new("pico_node", "", support)));
+    let mut publisher = defmt::unwrap!(RclPublisher::new(
+        node,
         unsafe { rosidl_typesupport_c__get_message_type_support_handle__std_msgs__msg__Int32() },
         "pico_publisher",
-    );
-    defmt::unwrap!(spawner.spawn(publisher_task(publisher)));
-
-    let mut executor = RclcExecutor::new(&mut support, 10, &mut allocator);
-
-    loop {
-        yield_now().await;
-        executor.spin();
-    }
-}
-
-#[embassy_executor::task]
-async fn publisher_task(mut publisher: RclPublisher) {
-    let a = unsafe { std_msgs__msg__Int32__create() };
-    loop {
-        Timer::after_millis(1000).await;
-        publisher.publish(a as _);
-        unsafe { (*a).data += 1 };
-    }
+    ));
+    let message = unsafe { std_msgs__msg__Int32__create() };
+    let timer = defmt::unwrap!(RclTimer::new(support, Duration::from_secs(1), move |_| {
+        if let Err(e) = publisher.publish(message as _) {
+            defmt::warn!("publishing failed: {}", e);
+        }
+        unsafe { (*message).data += 1 };
+    }));
+
+    let mut executor = defmt::unwrap!(RclcExecutor::new(support, 1, allocator));
+    defmt::unwrap!(executor.add_timer(&timer));
+
+    executor.run().await
 }
@@ -6,12 +6,12 @@ use defmt::*;
 use eir::microros;
 use eir::microros::Allocator;
 use eir::microros::RclNode;
-use eir::microros::RclServiceClient;
 use eir::microros::RclcExecutor;
 use eir::microros::RclcSupport;
+use eir::microros::TypedClient;
+use eir::msg::std_srvs::{SetBool, SetBoolRequest};
 use embassy_executor::InterruptExecutor;
 use embassy_executor::Spawner;
-use embassy_futures::yield_now;
 use embassy_rp::gpio;
 use embassy_rp::interrupt;
 use embassy_rp::interrupt::InterruptExt as _;
@@ -19,11 +19,6 @@ use embassy_rp::interrupt::Priority;
 use embassy_rp::Peripherals;
 use embassy_time::Timer;
 use gpio::{Level, Output};
-use microros_sys::rosidl_typesupport_c__get_service_type_support_handle__std_srvs__srv__SetBool;
-use microros_sys::std_srvs__srv__SetBool_Request;
-use microros_sys::std_srvs__srv__SetBool_Request__create;
-use microros_sys::std_srvs__srv__SetBool_Response;
-use microros_sys::std_srvs__srv__SetBool_Response__create;
 use static_cell::make_static;
 use {defmt_rtt as _, panic_probe as _};
 
@@ -60,55 +55,39 @@ async fn main(spawner: Spawner) {
 
     Timer::after_secs(1).await;
 
-    eir::transport::init_rmw_transport();
+    eir::transport::init_rmw_transport(make_static!(eir::transport::UsbTransport::new()));
 
-    let mut allocator = Allocator::default();
+    let allocator = make_static!(Allocator::default());
 
-    microros::wait_for_agent();
+    unwrap!(microros::wait_for_agent(microros::PingPolicy::default()).await);
 
-    let mut support = RclcSupport::new(&mut allocator);
-    let mut node = RclNode::new("pico_node", "", &mut support);
-    let mut executor = RclcExecutor::new(&mut support, 10, &mut allocator);
+    let support = make_static!(defmt::unwrap!(RclcSupport::new(allocator)));
+    let node = make_static!(defmt::unwrap!(RclNode::new("pico_node", "", support)));
 
-    let mut service_client = RclServiceClient::new(
-        &mut node,
-        unsafe { rosidl_typesupport_c__get_service_type_support_handle__std_srvs__srv__SetBool() },
-        "hello_srv",
-    );
+    let service_client = make_static!(defmt::unwrap!(TypedClient::<SetBool>::new(
+        node,
+        "hello_srv"
+    )));
 
-    executor.add_service_client(
-        &mut service_client,
-        unsafe { std_srvs__srv__SetBool_Response__create() as _ },
-        Some(service_client_callback),
-    );
+    let mut executor = defmt::unwrap!(RclcExecutor::new(support, 10, allocator));
 
-    defmt::unwrap!(spawner.spawn(service_client_task(service_client)));
-
-    loop {
-        yield_now().await;
-        executor.spin();
-    }
-}
+    defmt::unwrap!(executor.add_typed_client(service_client));
 
-extern "C" fn service_client_callback(resp: *const core::ffi::c_void) {
-    defmt::assert!(!resp.is_null());
-    defmt::assert!(resp.is_aligned());
-    let resp = resp as *const std_srvs__srv__SetBool_Response;
-    let resp: &std_srvs__srv__SetBool_Response = unsafe { &*resp as _ };
+    defmt::unwrap!(spawner.spawn(service_client_task(service_client)));
 
-    defmt::info!("received response: {}", resp.success);
+    executor.run().await
 }
 
 #[embassy_executor::task]
-async fn service_client_task(mut client: RclServiceClient) {
-    let sqn = make_static!(0i64);
-    let req = unsafe { std_srvs__srv__SetBool_Request__create() };
+async fn service_client_task(client: &'static TypedClient<'static, SetBool>) {
+    let mut req = SetBoolRequest::default();
 
     loop {
         Timer::after_secs(1).await;
-        client.send_request(req as _, sqn);
-        defmt::info!("req sent");
-        let req: &mut std_srvs__srv__SetBool_Request = unsafe { &mut *req as _ };
+        match client.call(&req).await {
+            Ok(resp) => defmt::info!("received response: {}", resp.success),
+            Err(e) => defmt::warn!("service call failed: {}", e),
+        }
         req.data = !req.data;
     }
 }
@@ -6,12 +6,12 @@ use defmt::*;
 use eir::microros;
 use eir::microros::Allocator;
 use eir::microros::RclNode;
-use eir::microros::RclService;
 use eir::microros::RclcExecutor;
 use eir::microros::RclcSupport;
+use eir::microros::TypedService;
+use eir::msg::std_srvs::{SetBool, SetBoolRequest, SetBoolResponse};
 use embassy_executor::InterruptExecutor;
 use embassy_executor::Spawner;
-use embassy_futures::yield_now;
 use embassy_rp::gpio;
 use embassy_rp::interrupt;
 use embassy_rp::interrupt::InterruptExt as _;
@@ -19,11 +19,7 @@ use embassy_rp::interrupt::Priority;
 use embassy_rp::Peripherals;
 use embassy_time::Timer;
 use gpio::{Level, Output};
-use microros_sys::rosidl_typesupport_c__get_service_type_support_handle__std_srvs__srv__SetBool;
-use microros_sys::std_srvs__srv__SetBool_Request;
-use microros_sys::std_srvs__srv__SetBool_Request__create;
-use microros_sys::std_srvs__srv__SetBool_Response;
-use microros_sys::std_srvs__srv__SetBool_Response__create;
+use static_cell::make_static;
 use {defmt_rtt as _, panic_probe as _};
 
 #[embassy_executor::task]
@@ -59,46 +55,26 @@ async fn main(_spawner: Spawner) {
 
     Timer::after_secs(1).await;
 
-    eir::transport::init_rmw_transport();
+    eir::transport::init_rmw_transport(make_static!(eir::transport::UsbTransport::new()));
 
-    let mut allocator = Allocator::default();
+    let allocator = Allocator::default();
 
-    microros::wait_for_agent();
+    unwrap!(microros::wait_for_agent(microros::PingPolicy::default()).await);
 
-    let mut support = RclcSupport::new(&mut allocator);
-    let mut node = RclNode::new("pico_node", "", &mut support);
+    let support = defmt::unwrap!(RclcSupport::new(&allocator));
+    let node = defmt::unwrap!(RclNode::new("pico_node", "", &support));
 
-    let mut executor = RclcExecutor::new(&mut support, 10, &mut allocator);
+    let mut service = defmt::unwrap!(TypedService::<SetBool>::new(&node, "pico_srv"));
 
-    let mut service = RclService::new(
-        &mut node,
-        unsafe { rosidl_typesupport_c__get_service_type_support_handle__std_srvs__srv__SetBool() },
-        "pico_srv",
-    );
+    let mut executor = defmt::unwrap!(RclcExecutor::new(&support, 10, &allocator));
 
-    executor.add_service(
+    defmt::unwrap!(executor.add_typed_service(
         &mut service,
-        unsafe { std_srvs__srv__SetBool_Request__create() as _ },
-        unsafe { std_srvs__srv__SetBool_Response__create() as _ },
-        Some(service_callback),
-    );
+        |req: &SetBoolRequest, resp: &mut SetBoolResponse| {
+            defmt::info!("service request: {}", req.data);
+            resp.success = true;
+        }
+    ));
 
-    loop {
-        yield_now().await;
-        executor.spin();
-    }
-}
-
-extern "C" fn service_callback(req: *const core::ffi::c_void, resp: *mut core::ffi::c_void) {
-    defmt::assert!(!req.is_null());
-    defmt::assert!(req.is_aligned());
-    let req = req as *const std_srvs__srv__SetBool_Request;
-    let req: &std_srvs__srv__SetBool_Request = unsafe { &(*req) };
-    defmt::info!("service request: {}", req.data);
-
-    defmt::assert!(!resp.is_null());
-    defmt::assert!(resp.is_aligned());
-    let resp = resp as *mut std_srvs__srv__SetBool_Response;
-    let resp: &mut std_srvs__srv__SetBool_Response = unsafe { &mut (*resp) };
-    resp.success = true;
+    executor.run().await
 }
@@ -6,12 +6,12 @@ use defmt::*;
 use eir::microros;
 use eir::microros::Allocator;
 use eir::microros::RclNode;
-use eir::microros::RclSubscription;
 use eir::microros::RclcExecutor;
 use eir::microros::RclcSupport;
+use eir::microros::TypedSubscription;
+use eir::msg::std_msgs::Int32;
 use embassy_executor::InterruptExecutor;
 use embassy_executor::Spawner;
-use embassy_futures::yield_now;
 use embassy_rp::gpio;
 use embassy_rp::interrupt;
 use embassy_rp::interrupt::InterruptExt as _;
@@ -19,9 +19,7 @@ use embassy_rp::interrupt::Priority;
 use embassy_rp::Peripherals;
 use embassy_time::Timer;
 use gpio::{Level, Output};
-use microros_sys::rosidl_typesupport_c__get_message_type_support_handle__std_msgs__msg__Int32;
-use microros_sys::std_msgs__msg__Int32;
-use microros_sys::std_msgs__msg__Int32__create;
+use static_cell::make_static;
 use {defmt_rtt as _, panic_probe as _};
 
 #[embassy_executor::task]
@@ -57,38 +55,25 @@ async fn main(_spawner: Spawner) {
 
     Timer::after_secs(1).await;
 
-    eir::transport::init_rmw_transport();
+    eir::transport::init_rmw_transport(make_static!(eir::transport::UsbTransport::new()));
 
-    let mut allocator = Allocator::default();
+    let allocator = Allocator::default();
 
-    microros::wait_for_agent();
+    unwrap!(microros::wait_for_agent(microros::PingPolicy::default()).await);
 
-    let mut support = RclcSupport::new(&mut allocator);
-    let mut node = RclNode::new("pico_node", "", &mut support);
+    let support = defmt::unwrap!(RclcSupport::new(&allocator));
+    let node = defmt::unwrap!(RclNode::new("pico_node", "", &support));
 
-    let mut subscription = RclSubscription::new(
-        &mut node,
-        unsafe { rosidl_typesupport_c__get_message_type_support_handle__std_msgs__msg__Int32() },
-        "pico_subscriber",
-    );
-
-    let mut executor = RclcExecutor::new(&mut support, 10, &mut allocator);
-
-    let sub_data = unsafe { std_msgs__msg__Int32__create() };
-
-    executor.add_subscription(&mut subscription, sub_data as _, Some(sub_callback));
+    let mut subscription =
+        defmt::unwrap!(TypedSubscription::<Int32>::new(&node, "pico_subscriber"));
 
-    loop {
-        yield_now().await;
-        executor.spin();
-    }
-}
+    let mut executor = defmt::unwrap!(RclcExecutor::new(&support, 10, &allocator));
 
-extern "C" fn sub_callback(data: *const core::ffi::c_void) {
-    defmt::assert!(!data.is_null());
-    defmt::assert!(data.is_aligned());
-    let data: *const std_msgs__msg__Int32 = data as _;
-    let data: &std_msgs__msg__Int32 = unsafe { &*data as _ };
+    defmt::unwrap!(
+        executor.add_typed_subscription(&mut subscription, |msg: &Int32| {
+            defmt::info!("received: {}", msg.data);
+        })
+    );
 
-    defmt::info!("received: {}", data.data);
+    executor.run().await
 }
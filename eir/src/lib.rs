@@ -1,9 +1,18 @@
-#![no_std]
+#![cfg_attr(not(feature = "std"), no_std)]
 #![feature(type_alias_impl_trait)]
 
+#[cfg(all(feature = "rp2040", feature = "std"))]
+compile_error!("The `rp2040` and `std` features are mutually exclusive, use `--no-default-features --features std` for the host build.");
+
+// must come first, the macros are only visible to the modules declared after it
+mod fmt;
+
+#[cfg(feature = "rp2040")]
 pub mod binary_compat;
 pub mod microros;
 pub mod msg;
+pub mod rosidl;
+#[cfg(feature = "rp2040")]
 pub mod smartled;
 pub mod transport;
 pub mod usb_serial;
@@ -1,196 +1,493 @@
-use core::{marker::PhantomData, mem::MaybeUninit, ptr};
+use core::{cell::UnsafeCell, ffi::c_char, marker::PhantomData, mem::MaybeUninit, ptr};
+
+use embassy_time::{Duration, Instant};
 
 use microros_sys::{
-    rcl_client_t, rcl_context_t, rcl_node_t, rcl_publish, rcl_publisher_t, rcl_send_request,
-    rcl_service_t, rcl_subscription_t, rclc_client_callback_t, rclc_client_init_default,
-    rclc_executor_add_client, rclc_executor_add_service, rclc_executor_add_subscription,
-    rclc_executor_handle_invocation_t_ALWAYS, rclc_executor_init, rclc_executor_spin_some,
-    rclc_executor_t, rclc_node_init_default, rclc_publisher_init_default, rclc_service_callback_t,
-    rclc_service_init_default, rclc_subscription_callback_t, rclc_subscription_init_default,
-    rclc_support_init, rclc_support_t, rcutils_allocator_t, rcutils_get_default_allocator,
-    rmw_uros_ping_agent, rosidl_message_type_support_t, rosidl_service_type_support_t, RCL_RET_OK,
+    rcl_client_fini, rcl_client_t, rcl_context_t, rcl_node_fini, rcl_node_t, rcl_publish,
+    rcl_publisher_fini, rcl_publisher_t, rcl_ret_t, rcl_send_request, rcl_service_fini,
+    rcl_service_t, rcl_subscription_fini, rcl_subscription_t, rclc_client_callback_t,
+    rclc_client_init_default, rclc_executor_add_client, rclc_executor_add_client_with_request_id,
+    rclc_executor_add_service, rclc_executor_add_service_with_context,
+    rclc_executor_add_subscription, rclc_executor_add_subscription_with_context,
+    rclc_executor_add_timer, rclc_executor_fini, rclc_executor_handle_invocation_t_ALWAYS,
+    rclc_executor_handle_invocation_t_ON_NEW_DATA, rclc_executor_init, rclc_executor_set_timeout,
+    rclc_executor_set_trigger, rclc_executor_spin_one_period, rclc_executor_spin_some,
+    rclc_executor_t, rclc_executor_trigger_all, rclc_executor_trigger_always,
+    rclc_executor_trigger_any, rclc_executor_trigger_t, rclc_node_init_default,
+    rclc_publisher_init, rclc_publisher_init_best_effort, rclc_publisher_init_default,
+    rclc_service_callback_t, rclc_service_init_default, rclc_subscription_callback_t,
+    rclc_subscription_init, rclc_subscription_init_best_effort, rclc_subscription_init_default,
+    rclc_support_fini, rclc_support_init, rclc_support_t, rcutils_allocator_t,
+    rcutils_get_default_allocator, rosidl_message_type_support_t, rosidl_service_type_support_t,
 };
 
-/// Wait for an agent on the host to be available
-/// This blocks the current "thread", so the higher priority transport must be running now
-pub fn wait_for_agent() {
-    defmt::info!("waiting for agent");
-    let ret = unsafe { rmw_uros_ping_agent(1000, 10) } as u32;
-    defmt::info!("waiting for agent ended {}", ret);
-    if ret != RCL_RET_OK {
-        defmt::panic!("agent probing failed");
-    }
-}
+use crate::msg::{Message, Service};
+use crate::transport;
+
+mod callbacks;
+mod client;
+mod error;
+mod ping;
+mod pool;
+mod qos;
+mod supervisor;
+mod timer;
+mod trigger;
+
+use callbacks::CallbackList;
+pub use client::{TypedClient, DEFAULT_CALL_TIMEOUT, MAX_PENDING_CALLS, MAX_TYPED_CLIENTS};
+pub use error::{Error, ErrorMessage, ReturnCode};
+pub use ping::{wait_for_agent, AgentUnavailable, PingPolicy};
+pub use pool::PoolStats;
+pub use qos::{Durability, History, QosProfile, Reliability};
+pub use supervisor::{
+    AgentState, AgentSupervisor, Connection, Session, DEFAULT_PING_PERIOD, PING_TIMEOUT,
+};
+pub use timer::{RclTimer, MAX_TIMERS};
+pub use trigger::{ReadyHandles, Trigger};
 
 pub struct Allocator {
     inner: rcutils_allocator_t,
+    pool: Option<&'static pool::Pool>,
 }
 
 impl Allocator {
+    /// Serves all allocations from `memory` instead of the `malloc` heap, failing once it is
+    /// exhausted. Part of `memory` holds the bookkeeping, returns `None` if it does not fit.
+    ///
+    /// The message wrappers of `msg` are still allocated by rosidl through the default allocator.
+    pub fn from_pool(memory: &'static mut [u8]) -> Option<Self> {
+        let pool = pool::Pool::new(memory)?;
+        Some(Self {
+            inner: rcutils_allocator_t {
+                allocate: Some(pool::allocate),
+                deallocate: Some(pool::deallocate),
+                reallocate: Some(pool::reallocate),
+                zero_allocate: Some(pool::zero_allocate),
+                state: pool as *const pool::Pool as _,
+            },
+            pool: Some(pool),
+        })
+    }
+
+    /// Usage of the pool, `None` for the default allocator
+    pub fn pool_stats(&self) -> Option<PoolStats> {
+        self.pool.map(|pool| pool.stats())
+    }
+
+    pub fn as_ptr(&self) -> *const rcutils_allocator_t {
+        &self.inner as _
+    }
+
     pub fn as_mut_ptr(&mut self) -> *mut rcutils_allocator_t {
         &mut self.inner as _
     }
+
+    /// Allocates `size` bytes using the rcutils allocator, returns null on failure
+    pub(crate) fn allocate(&self, size: usize) -> *mut core::ffi::c_void {
+        match self.inner.allocate {
+            Some(allocate) => unsafe { allocate(size, self.inner.state) },
+            None => ptr::null_mut(),
+        }
+    }
+
+    /// Grows or shrinks memory obtained from `allocate`, returns null on failure
+    pub(crate) unsafe fn reallocate(
+        &self,
+        pointer: *mut core::ffi::c_void,
+        size: usize,
+    ) -> *mut core::ffi::c_void {
+        match self.inner.reallocate {
+            Some(reallocate) => reallocate(pointer, size, self.inner.state),
+            None => ptr::null_mut(),
+        }
+    }
+
+    /// Returns memory obtained from `allocate` back to the rcutils allocator
+    pub(crate) unsafe fn deallocate(&self, pointer: *mut core::ffi::c_void) {
+        if let Some(deallocate) = self.inner.deallocate {
+            deallocate(pointer, self.inner.state)
+        }
+    }
 }
 
 impl Default for Allocator {
     fn default() -> Self {
         Self {
             inner: unsafe { rcutils_get_default_allocator() },
+            pool: None,
         }
     }
 }
 
-pub struct RclcSupport {
-    inner: rclc_support_t,
+/// rclc support structure holding the rcl context.
+/// Nodes and executors keep a pointer to the context, so they borrow the support.
+pub struct RclcSupport<'a> {
+    // rcl writes through the pointers handed out by shared references
+    inner: UnsafeCell<rclc_support_t>,
+    _allocator: PhantomData<&'a Allocator>,
 }
 
-impl RclcSupport {
-    pub fn new(allocator: &mut Allocator) -> Self {
+impl<'a> RclcSupport<'a> {
+    pub fn new(allocator: &'a Allocator) -> Result<Self, Error> {
         let mut raw: MaybeUninit<rclc_support_t> = MaybeUninit::uninit();
-        // TODO: this can fail
-        unsafe { rclc_support_init(raw.as_mut_ptr(), 0, ptr::null(), allocator.as_mut_ptr()) };
+        // Note(safety): rclc only stores the allocator pointer, it never writes through it
+        Error::check(unsafe {
+            rclc_support_init(raw.as_mut_ptr(), 0, ptr::null(), allocator.as_ptr() as _)
+        })?;
+
+        Ok(Self {
+            inner: UnsafeCell::new(unsafe { raw.assume_init() }),
+            _allocator: PhantomData,
+        })
+    }
 
-        Self {
-            inner: unsafe { raw.assume_init() },
-        }
+    fn as_ptr(&self) -> *mut rclc_support_t {
+        self.inner.get()
     }
 
-    fn as_mut_ptr(&mut self) -> *mut rclc_support_t {
-        &mut self.inner as _
+    pub(crate) fn context(&self) -> *mut rcl_context_t {
+        unsafe { ptr::addr_of_mut!((*self.inner.get()).context) }
+    }
+}
+
+impl Drop for RclcSupport<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = Error::check(unsafe { rclc_support_fini(self.inner.get_mut()) }) {
+            warn!("failed to finalize support: {:?}", e);
+        }
     }
 }
 
-pub struct RclNode {
-    inner: rcl_node_t,
+pub struct RclNode<'a> {
+    inner: UnsafeCell<rcl_node_t>,
+    _support: PhantomData<&'a ()>,
 }
 
-impl RclNode {
-    pub fn new(node_name: &str, namespace: &str, support: &mut RclcSupport) -> Self {
+impl<'a> RclNode<'a> {
+    pub fn new(
+        node_name: &str,
+        namespace: &str,
+        support: &'a RclcSupport<'_>,
+    ) -> Result<Self, Error> {
         let mut raw: MaybeUninit<rcl_node_t> = MaybeUninit::uninit();
         let mut node_name_buf = [0u8; 100]; // TODO: extract to constants
         let mut namespace_buf = [0u8; 100];
         // Note(safety): these are wild assumption about lifetimes of the buffers, but from a quick
         // glance at the code in rcl, it seems like the function then allocates the strings on a
         // heap
-        unsafe {
+        Error::check(unsafe {
             rclc_node_init_default(
                 raw.as_mut_ptr(),
-                util::create_null_terminated_string(node_name, &mut node_name_buf),
-                util::create_null_terminated_string(namespace, &mut namespace_buf),
-                support.as_mut_ptr(),
-            );
-        }
-        Self {
-            inner: unsafe { raw.assume_init() },
-        }
+                util::create_null_terminated_string(node_name, &mut node_name_buf)?,
+                util::create_null_terminated_string(namespace, &mut namespace_buf)?,
+                support.as_ptr(),
+            )
+        })?;
+        Ok(Self {
+            inner: UnsafeCell::new(unsafe { raw.assume_init() }),
+            _support: PhantomData,
+        })
     }
 
-    fn as_mut_ptr(&mut self) -> *mut rcl_node_t {
-        &mut self.inner as _
+    fn as_ptr(&self) -> *mut rcl_node_t {
+        self.inner.get()
     }
 }
 
-pub struct RclPublisher {
+impl Drop for RclNode<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = Error::check(unsafe { rcl_node_fini(self.inner.get_mut()) }) {
+            warn!("failed to finalize node: {:?}", e);
+        }
+    }
+}
+
+pub struct RclPublisher<'a> {
     inner: rcl_publisher_t,
+    node: &'a RclNode<'a>,
 }
 
-impl RclPublisher {
+impl<'a> RclPublisher<'a> {
     pub fn new(
-        node: &mut RclNode,
+        node: &'a RclNode<'a>,
+        message_type: *const rosidl_message_type_support_t,
+        topic_name: &str,
+    ) -> Result<Self, Error> {
+        Self::init(
+            node,
+            message_type,
+            topic_name,
+            |raw, node, ty, topic| unsafe { rclc_publisher_init_default(raw, node, ty, topic) },
+        )
+    }
+
+    pub fn with_qos(
+        node: &'a RclNode<'a>,
+        message_type: *const rosidl_message_type_support_t,
+        topic_name: &str,
+        qos: QosProfile,
+    ) -> Result<Self, Error> {
+        let qos = qos.to_rmw();
+        Self::init(
+            node,
+            message_type,
+            topic_name,
+            |raw, node, ty, topic| unsafe { rclc_publisher_init(raw, node, ty, topic, &qos) },
+        )
+    }
+
+    /// Publishes on the best-effort stream, samples are not resent when lost
+    pub fn best_effort(
+        node: &'a RclNode<'a>,
         message_type: *const rosidl_message_type_support_t,
         topic_name: &str,
-    ) -> Self {
+    ) -> Result<Self, Error> {
+        Self::init(
+            node,
+            message_type,
+            topic_name,
+            |raw, node, ty, topic| unsafe { rclc_publisher_init_best_effort(raw, node, ty, topic) },
+        )
+    }
+
+    /// Creates the publisher with `init`, which gets the uninitialized publisher, the node, the
+    /// type support and the null terminated topic name
+    fn init(
+        node: &'a RclNode<'a>,
+        message_type: *const rosidl_message_type_support_t,
+        topic_name: &str,
+        init: impl FnOnce(
+            *mut rcl_publisher_t,
+            *mut rcl_node_t,
+            *const rosidl_message_type_support_t,
+            *const c_char,
+        ) -> rcl_ret_t,
+    ) -> Result<Self, Error> {
         let mut raw: MaybeUninit<rcl_publisher_t> = MaybeUninit::uninit();
 
         let mut topic_name_buffer = [0u8; 100];
-
-        unsafe {
-            rclc_publisher_init_default(
-                raw.as_mut_ptr(),
-                node.as_mut_ptr(),
-                message_type,
-                util::create_null_terminated_string(topic_name, &mut topic_name_buffer),
-            );
-        }
-        Self {
+        let topic_name = util::create_null_terminated_string(topic_name, &mut topic_name_buffer)?;
+
+        Error::check(init(
+            raw.as_mut_ptr(),
+            node.as_ptr(),
+            message_type,
+            topic_name,
+        ))?;
+        Ok(Self {
             inner: unsafe { raw.assume_init() },
-        }
+            node,
+        })
     }
 
     fn as_mut_ptr(&mut self) -> *mut rcl_publisher_t {
         &mut self.inner as _
     }
 
-    pub fn publish(&mut self, data: *const core::ffi::c_void) {
-        unsafe { rcl_publish(self.as_mut_ptr(), data, core::ptr::null_mut()) };
+    pub fn publish(&mut self, data: *const core::ffi::c_void) -> Result<(), Error> {
+        Error::check(unsafe { rcl_publish(self.as_mut_ptr(), data, core::ptr::null_mut()) })
     }
 }
 
-pub struct TypedPublisher<T> {
+impl Drop for RclPublisher<'_> {
+    fn drop(&mut self) {
+        let ret = unsafe { rcl_publisher_fini(&mut self.inner, self.node.as_ptr()) };
+        if let Err(e) = Error::check(ret) {
+            warn!("failed to finalize publisher: {:?}", e);
+        }
+    }
+}
+
+pub struct TypedPublisher<'a, T> {
     _phantom: PhantomData<T>,
-    inner: RclPublisher,
+    inner: RclPublisher<'a>,
 }
 
-impl<T> TypedPublisher<T>
+impl<'a, T> TypedPublisher<'a, T>
 where
-    T: crate::msg::Message,
+    T: Message,
 {
-    pub fn new(node: &mut RclNode, topic_name: &str) -> Self {
-        Self {
+    pub fn new(node: &'a RclNode<'a>, topic_name: &str) -> Result<Self, Error> {
+        Ok(Self {
             _phantom: PhantomData,
-            inner: RclPublisher::new(node, unsafe { T::rosidl_type_support() }, topic_name),
-        }
+            inner: RclPublisher::new(node, unsafe { T::rosidl_type_support() }, topic_name)?,
+        })
     }
 
-    pub fn publish(&mut self, msg: &T) {
+    pub fn with_qos(
+        node: &'a RclNode<'a>,
+        topic_name: &str,
+        qos: QosProfile,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            _phantom: PhantomData,
+            inner: RclPublisher::with_qos(
+                node,
+                unsafe { T::rosidl_type_support() },
+                topic_name,
+                qos,
+            )?,
+        })
+    }
+
+    pub fn best_effort(node: &'a RclNode<'a>, topic_name: &str) -> Result<Self, Error> {
+        Ok(Self {
+            _phantom: PhantomData,
+            inner: RclPublisher::best_effort(
+                node,
+                unsafe { T::rosidl_type_support() },
+                topic_name,
+            )?,
+        })
+    }
+
+    pub fn publish(&mut self, msg: &T) -> Result<(), Error> {
         self.inner.publish(msg.erased_ptr())
     }
 }
 
-pub struct RclcExecutor {
+/// How often `RclcExecutor::run` checks for data when the transport does not notify it
+pub const RUN_POLL_PERIOD: Duration = Duration::from_millis(10);
+
+/// The executor stores pointers to the added handles, so these must outlive the executor.
+/// Closures registered through the typed API are owned by the executor.
+pub struct RclcExecutor<'a> {
     inner: rclc_executor_t,
+    allocator: &'a Allocator,
+    callbacks: CallbackList,
+    /// Predicate of `set_trigger_fn`, kept apart so that setting another trigger frees it
+    trigger: CallbackList,
 }
 
-impl RclcExecutor {
+impl<'a> RclcExecutor<'a> {
     pub fn new(
-        support: &mut RclcSupport,
+        support: &'a RclcSupport<'_>,
         number_of_handles: usize,
-        allocator: &mut Allocator,
-    ) -> Self {
+        allocator: &'a Allocator,
+    ) -> Result<Self, Error> {
         let mut raw: MaybeUninit<rclc_executor_t> = MaybeUninit::uninit();
 
-        unsafe {
-            let support: *mut rclc_support_t = support.as_mut_ptr();
-            let context: *mut rcl_context_t = &mut (*support).context;
-
+        Error::check(unsafe {
             rclc_executor_init(
                 raw.as_mut_ptr(),
-                context,
+                support.context(),
                 number_of_handles,
-                allocator.as_mut_ptr(),
-            );
-        }
+                allocator.as_ptr(),
+            )
+        })?;
 
-        Self {
+        Ok(Self {
             inner: unsafe { raw.assume_init() },
-        }
+            allocator,
+            callbacks: CallbackList::new(),
+            trigger: CallbackList::new(),
+        })
     }
 
     fn as_mut_ptr(&mut self) -> *mut rclc_executor_t {
         &mut self.inner as _
     }
 
-    pub fn spin(&mut self) {
-        unsafe { rclc_executor_spin_some(self.as_mut_ptr(), 100 * 1000 * 1000) };
+    /// Processes the ready handles, waiting at most 100 ms for new data.
+    /// Running out of the timeout without any work is not considered an error.
+    pub fn spin(&mut self) -> Result<(), Error> {
+        self.spin_some(Duration::from_millis(100))
+    }
+
+    /// Processes the ready handles, waiting at most `timeout` for new data.
+    /// Running out of the timeout without any work is not considered an error.
+    pub fn spin_some(&mut self, timeout: Duration) -> Result<(), Error> {
+        let ret = unsafe { rclc_executor_spin_some(self.as_mut_ptr(), nanos(timeout)) };
+        ignore_timeout(ret)
+    }
+
+    /// Spins once and then blocks until `period` passed since the previous call, for control
+    /// loops running at a fixed rate. The spin waits for data up to the timeout set with
+    /// `set_timeout`, which should be shorter than `period`.
+    pub fn spin_period(&mut self, period: Duration) -> Result<(), Error> {
+        let ret = unsafe { rclc_executor_spin_one_period(self.as_mut_ptr(), nanos(period)) };
+        ignore_timeout(ret)
+    }
+
+    /// Processes the ready handles until `deadline` passes
+    pub fn spin_until(&mut self, deadline: Instant) -> Result<(), Error> {
+        loop {
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(());
+            }
+            self.spin_some(deadline - now)?;
+        }
+    }
+
+    /// Sets how long the spins of `spin_period` wait for data
+    pub fn set_timeout(&mut self, timeout: Duration) -> Result<(), Error> {
+        Error::check(unsafe { rclc_executor_set_timeout(self.as_mut_ptr(), nanos(timeout)) })
+    }
+
+    /// Sets the condition under which a spin processes the ready handles
+    pub fn set_trigger(&mut self, trigger: Trigger) -> Result<(), Error> {
+        let (function, object): (rclc_executor_trigger_t, *mut core::ffi::c_void) = match trigger {
+            Trigger::Any => (Some(rclc_executor_trigger_any), ptr::null_mut()),
+            Trigger::All => (Some(rclc_executor_trigger_all), ptr::null_mut()),
+            Trigger::Always => (Some(rclc_executor_trigger_always), ptr::null_mut()),
+            Trigger::One(index) => (Some(trigger::one_trampoline), index as _),
+        };
+        Error::check(unsafe { rclc_executor_set_trigger(self.as_mut_ptr(), function, object) })?;
+        // rclc no longer references a predicate set before
+        self.trigger.clear(self.allocator);
+        Ok(())
+    }
+
+    /// Processes the ready handles only when `predicate` returns true.
+    /// The predicate is owned by the executor and freed once another trigger is set.
+    pub fn set_trigger_fn<F>(&mut self, predicate: F) -> Result<(), Error>
+    where
+        F: FnMut(&ReadyHandles<'_>) -> bool + 'a,
+    {
+        let mut predicates = CallbackList::new();
+        let context = predicates.push(self.allocator, predicate)?;
+        let ret = unsafe {
+            rclc_executor_set_trigger(
+                self.as_mut_ptr(),
+                Some(trigger::closure_trampoline::<F>),
+                context as _,
+            )
+        };
+        if let Err(e) = Error::check(ret) {
+            predicates.clear(self.allocator);
+            return Err(e);
+        }
+
+        self.trigger.clear(self.allocator);
+        self.trigger = predicates;
+        Ok(())
+    }
+
+    /// Processes the ready handles whenever the transport received data and sleeps in between,
+    /// letting the other tasks of the executor run. Never returns, failed spins are logged.
+    ///
+    /// Transports wake the executor through `transport::notify_received`, the ones that don't
+    /// are polled every `RUN_POLL_PERIOD`.
+    pub async fn run(&mut self) -> ! {
+        loop {
+            // a notification arriving from here on makes the wait below return right away
+            transport::reset_received();
+            if let Err(e) = self.spin_some(Duration::from_ticks(0)) {
+                warn!("executor spin failed: {:?}", e);
+            }
+            transport::wait_received(RUN_POLL_PERIOD).await;
+        }
     }
 
     pub fn add_subscription(
         &mut self,
-        subscription: &mut RclSubscription,
+        subscription: &'a mut RclSubscription<'_>,
         message: *mut core::ffi::c_void,
         callback: rclc_subscription_callback_t,
-    ) {
-        unsafe {
+    ) -> Result<(), Error> {
+        Error::check(unsafe {
             rclc_executor_add_subscription(
                 self.as_mut_ptr(),
                 subscription.as_mut_ptr(),
@@ -198,70 +495,260 @@ impl RclcExecutor {
                 callback,
                 rclc_executor_handle_invocation_t_ALWAYS,
             )
-        };
+        })
+    }
+
+    /// Registers the subscription and calls `callback` with every newly received message
+    pub fn add_typed_subscription<T, F>(
+        &mut self,
+        subscription: &'a mut TypedSubscription<'_, T>,
+        callback: F,
+    ) -> Result<(), Error>
+    where
+        T: Message + 'a,
+        F: FnMut(&T) + 'a,
+    {
+        let context = self.callbacks.push(
+            self.allocator,
+            SubscriptionContext {
+                message: &subscription.message as *const T,
+                callback,
+            },
+        )?;
+
+        Error::check(unsafe {
+            rclc_executor_add_subscription_with_context(
+                self.as_mut_ptr(),
+                subscription.inner.as_mut_ptr(),
+                subscription.message.erased_mut_ptr(),
+                Some(subscription_trampoline::<T, F>),
+                context as _,
+                rclc_executor_handle_invocation_t_ON_NEW_DATA,
+            )
+        })
     }
 
     pub fn add_service(
         &mut self,
-        service: &mut RclService,
+        service: &'a mut RclService<'_>,
         request_msg: *mut core::ffi::c_void,
         response_msg: *mut core::ffi::c_void,
         callback: rclc_service_callback_t,
-    ) {
-        unsafe {
+    ) -> Result<(), Error> {
+        Error::check(unsafe {
             rclc_executor_add_service(
                 self.as_mut_ptr(),
                 service.as_mut_ptr(),
                 request_msg,
                 response_msg,
                 callback,
-            );
-        }
+            )
+        })
+    }
+
+    /// Registers the service and answers every request by calling `handler`.
+    /// The request and response buffers are owned by the executor.
+    pub fn add_typed_service<S, F>(
+        &mut self,
+        service: &'a mut TypedService<'_, S>,
+        handler: F,
+    ) -> Result<(), Error>
+    where
+        S: Service + 'a,
+        F: FnMut(&S::Request, &mut S::Response) + 'a,
+    {
+        let context: *mut ServiceContext<S, F> = self.callbacks.push(
+            self.allocator,
+            ServiceContext {
+                request: S::Request::default(),
+                response: S::Response::default(),
+                handler,
+            },
+        )?;
+
+        Error::check(unsafe {
+            rclc_executor_add_service_with_context(
+                self.as_mut_ptr(),
+                service.inner.as_mut_ptr(),
+                (*context).request.erased_mut_ptr(),
+                (*context).response.erased_mut_ptr(),
+                Some(service_trampoline::<S, F>),
+                context as _,
+            )
+        })
     }
 
+    /// The client is borrowed immutably, so that requests can still be sent through it
     pub fn add_service_client(
         &mut self,
-        client: &mut RclServiceClient,
+        client: &'a RclServiceClient<'_>,
         response_msg: *mut core::ffi::c_void,
         callback: rclc_client_callback_t,
-    ) {
-        unsafe {
-            rclc_executor_add_client(
+    ) -> Result<(), Error> {
+        Error::check(unsafe {
+            rclc_executor_add_client(self.as_mut_ptr(), client.as_ptr(), response_msg, callback)
+        })
+    }
+
+    /// Registers the client, so that the responses to its calls get delivered.
+    /// Like with `add_service_client`, the client stays usable for sending requests.
+    pub fn add_typed_client<S>(&mut self, client: &'a TypedClient<'_, S>) -> Result<(), Error>
+    where
+        S: Service + 'a,
+    {
+        let registration = client::Registration::new(client)?;
+        self.callbacks.push(self.allocator, registration)?;
+
+        Error::check(unsafe {
+            rclc_executor_add_client_with_request_id(
                 self.as_mut_ptr(),
-                client.as_mut_ptr(),
-                response_msg,
-                callback,
-            );
+                client.as_rcl_client().as_ptr(),
+                client.response_ptr(),
+                Some(client::response_trampoline::<S>),
+            )
+        })
+    }
+
+    /// Registers the timer, so that its callback gets called by the spins once the period passed.
+    /// The timer stays usable for resetting, canceling and changing the period.
+    pub fn add_timer<F>(&mut self, timer: &'a RclTimer<'_, F>) -> Result<(), Error>
+    where
+        F: FnMut(Duration) + 'a,
+    {
+        let registration = timer::Registration::new(timer)?;
+        self.callbacks.push(self.allocator, registration)?;
+
+        Error::check(unsafe { rclc_executor_add_timer(self.as_mut_ptr(), timer.as_ptr()) })
+    }
+}
+
+impl Drop for RclcExecutor<'_> {
+    fn drop(&mut self) {
+        if let Err(e) = Error::check(unsafe { rclc_executor_fini(&mut self.inner) }) {
+            warn!("failed to finalize executor: {:?}", e);
         }
+        self.callbacks.clear(self.allocator);
+        self.trigger.clear(self.allocator);
     }
 }
 
-pub struct RclSubscription {
+struct SubscriptionContext<T, F> {
+    message: *const T,
+    callback: F,
+}
+
+unsafe extern "C" fn subscription_trampoline<T, F>(
+    msg: *const core::ffi::c_void,
+    context: *mut core::ffi::c_void,
+) where
+    F: FnMut(&T),
+{
+    // the handle is registered as ON_NEW_DATA, so this should never happen
+    if msg.is_null() {
+        return;
+    }
+    let context = &mut *(context as *mut SubscriptionContext<T, F>);
+    (context.callback)(&*context.message);
+}
+
+struct ServiceContext<S: Service, F> {
+    request: S::Request,
+    response: S::Response,
+    handler: F,
+}
+
+unsafe extern "C" fn service_trampoline<S, F>(
+    _request: *const core::ffi::c_void,
+    _response: *mut core::ffi::c_void,
+    context: *mut core::ffi::c_void,
+) where
+    S: Service,
+    F: FnMut(&S::Request, &mut S::Response),
+{
+    // the raw pointers point into the buffers owned by the context
+    let context = &mut *(context as *mut ServiceContext<S, F>);
+    (context.handler)(&context.request, &mut context.response);
+}
+
+pub struct RclSubscription<'a> {
     inner: rcl_subscription_t,
+    node: &'a RclNode<'a>,
 }
 
-impl RclSubscription {
+impl<'a> RclSubscription<'a> {
     pub fn new(
-        node: &mut RclNode,
+        node: &'a RclNode<'a>,
+        message_type: *const rosidl_message_type_support_t,
+        topic_name: &str,
+    ) -> Result<Self, Error> {
+        Self::init(
+            node,
+            message_type,
+            topic_name,
+            |raw, node, ty, topic| unsafe { rclc_subscription_init_default(raw, node, ty, topic) },
+        )
+    }
+
+    pub fn with_qos(
+        node: &'a RclNode<'a>,
+        message_type: *const rosidl_message_type_support_t,
+        topic_name: &str,
+        qos: QosProfile,
+    ) -> Result<Self, Error> {
+        let qos = qos.to_rmw();
+        Self::init(
+            node,
+            message_type,
+            topic_name,
+            |raw, node, ty, topic| unsafe { rclc_subscription_init(raw, node, ty, topic, &qos) },
+        )
+    }
+
+    /// Receives on the best-effort stream, samples lost on the way are not resent
+    pub fn best_effort(
+        node: &'a RclNode<'a>,
         message_type: *const rosidl_message_type_support_t,
         topic_name: &str,
-    ) -> Self {
+    ) -> Result<Self, Error> {
+        Self::init(
+            node,
+            message_type,
+            topic_name,
+            |raw, node, ty, topic| unsafe {
+                rclc_subscription_init_best_effort(raw, node, ty, topic)
+            },
+        )
+    }
+
+    /// Creates the subscription with `init`, which gets the uninitialized subscription, the node,
+    /// the type support and the null terminated topic name
+    fn init(
+        node: &'a RclNode<'a>,
+        message_type: *const rosidl_message_type_support_t,
+        topic_name: &str,
+        init: impl FnOnce(
+            *mut rcl_subscription_t,
+            *mut rcl_node_t,
+            *const rosidl_message_type_support_t,
+            *const c_char,
+        ) -> rcl_ret_t,
+    ) -> Result<Self, Error> {
         let mut raw = MaybeUninit::uninit();
 
         let mut topic_name_buffer = [0u8; 100];
+        let topic_name = util::create_null_terminated_string(topic_name, &mut topic_name_buffer)?;
 
-        unsafe {
-            rclc_subscription_init_default(
-                raw.as_mut_ptr(),
-                node.as_mut_ptr(),
-                message_type,
-                util::create_null_terminated_string(topic_name, &mut topic_name_buffer),
-            );
-        }
+        Error::check(init(
+            raw.as_mut_ptr(),
+            node.as_ptr(),
+            message_type,
+            topic_name,
+        ))?;
 
-        Self {
+        Ok(Self {
             inner: unsafe { raw.assume_init() },
-        }
+            node,
+        })
     }
 
     pub fn as_mut_ptr(&mut self) -> *mut rcl_subscription_t {
@@ -269,29 +756,85 @@ impl RclSubscription {
     }
 }
 
-pub struct RclService {
+impl Drop for RclSubscription<'_> {
+    fn drop(&mut self) {
+        let ret = unsafe { rcl_subscription_fini(&mut self.inner, self.node.as_ptr()) };
+        if let Err(e) = Error::check(ret) {
+            warn!("failed to finalize subscription: {:?}", e);
+        }
+    }
+}
+
+/// Subscription owning the buffer the received messages are deserialized into
+pub struct TypedSubscription<'a, T> {
+    inner: RclSubscription<'a>,
+    message: T,
+}
+
+impl<'a, T> TypedSubscription<'a, T>
+where
+    T: Message + Default,
+{
+    pub fn new(node: &'a RclNode<'a>, topic_name: &str) -> Result<Self, Error> {
+        Ok(Self {
+            inner: RclSubscription::new(node, unsafe { T::rosidl_type_support() }, topic_name)?,
+            message: T::default(),
+        })
+    }
+
+    pub fn with_qos(
+        node: &'a RclNode<'a>,
+        topic_name: &str,
+        qos: QosProfile,
+    ) -> Result<Self, Error> {
+        Ok(Self {
+            inner: RclSubscription::with_qos(
+                node,
+                unsafe { T::rosidl_type_support() },
+                topic_name,
+                qos,
+            )?,
+            message: T::default(),
+        })
+    }
+
+    pub fn best_effort(node: &'a RclNode<'a>, topic_name: &str) -> Result<Self, Error> {
+        Ok(Self {
+            inner: RclSubscription::best_effort(
+                node,
+                unsafe { T::rosidl_type_support() },
+                topic_name,
+            )?,
+            message: T::default(),
+        })
+    }
+}
+
+pub struct RclService<'a> {
     inner: rcl_service_t,
+    node: &'a RclNode<'a>,
 }
 
-impl RclService {
+impl<'a> RclService<'a> {
     pub fn new(
-        node: &mut RclNode,
+        node: &'a RclNode<'a>,
         service_type: *const rosidl_service_type_support_t,
         name: &str,
-    ) -> Self {
+    ) -> Result<Self, Error> {
         let mut raw = MaybeUninit::uninit();
         let mut name_buffer = [0u8; 100];
-        unsafe {
+        Error::check(unsafe {
             rclc_service_init_default(
                 raw.as_mut_ptr(),
-                node.as_mut_ptr(),
+                node.as_ptr(),
                 service_type,
-                util::create_null_terminated_string(name, &mut name_buffer),
-            );
-        }
-        Self {
+                util::create_null_terminated_string(name, &mut name_buffer)?,
+            )
+        })?;
+        Ok(Self {
             inner: unsafe { raw.assume_init() },
-        }
+            node,
+        })
     }
 
     fn as_mut_ptr(&mut self) -> *mut rcl_service_t {
@@ -299,52 +842,110 @@ impl RclService {
     }
 }
 
-pub struct RclServiceClient {
-    inner: rcl_client_t,
+impl Drop for RclService<'_> {
+    fn drop(&mut self) {
+        let ret = unsafe { rcl_service_fini(&mut self.inner, self.node.as_ptr()) };
+        if let Err(e) = Error::check(ret) {
+            warn!("failed to finalize service: {:?}", e);
+        }
+    }
 }
 
-impl RclServiceClient {
+pub struct TypedService<'a, S> {
+    _phantom: PhantomData<S>,
+    inner: RclService<'a>,
+}
+
+impl<'a, S> TypedService<'a, S>
+where
+    S: Service,
+{
+    pub fn new(node: &'a RclNode<'a>, name: &str) -> Result<Self, Error> {
+        Ok(Self {
+            _phantom: PhantomData,
+            inner: RclService::new(node, unsafe { S::rosidl_type_support() }, name)?,
+        })
+    }
+}
+
+pub struct RclServiceClient<'a> {
+    inner: UnsafeCell<rcl_client_t>,
+    node: &'a RclNode<'a>,
+}
+
+impl<'a> RclServiceClient<'a> {
     pub fn new(
-        node: &mut RclNode,
+        node: &'a RclNode<'a>,
         type_support: *const rosidl_service_type_support_t,
         name: &str,
-    ) -> Self {
+    ) -> Result<Self, Error> {
         let mut raw = MaybeUninit::uninit();
 
         let mut name_buffer = [0u8; 100];
 
-        unsafe {
+        Error::check(unsafe {
             rclc_client_init_default(
                 raw.as_mut_ptr(),
-                node.as_mut_ptr(),
+                node.as_ptr(),
                 type_support,
-                util::create_null_terminated_string(name, &mut name_buffer),
-            );
-        }
+                util::create_null_terminated_string(name, &mut name_buffer)?,
+            )
+        })?;
 
-        Self {
-            inner: unsafe { raw.assume_init() },
-        }
+        Ok(Self {
+            inner: UnsafeCell::new(unsafe { raw.assume_init() }),
+            node,
+        })
     }
 
-    fn as_mut_ptr(&mut self) -> *mut rcl_client_t {
-        &mut self.inner as _
+    fn as_ptr(&self) -> *mut rcl_client_t {
+        self.inner.get()
     }
 
-    // TODO: wild assumptions about seq lifetime
-    pub fn send_request(&mut self, message: *const core::ffi::c_void, seq: &mut i64) {
-        unsafe {
-            rcl_send_request(self.as_mut_ptr(), message, seq as _);
+    /// Sends the request `message` points to and stores its sequence number in `seq`
+    pub fn send_request(
+        &self,
+        message: *const core::ffi::c_void,
+        seq: &mut i64,
+    ) -> Result<(), Error> {
+        // SAFETY: rcl serializes `message` and writes the sequence number into `seq` before
+        // returning, it keeps no pointer to either, so both only have to live for the call
+        Error::check(unsafe { rcl_send_request(self.as_ptr(), message, seq as _) })
+    }
+}
+
+impl Drop for RclServiceClient<'_> {
+    fn drop(&mut self) {
+        let ret = unsafe { rcl_client_fini(self.inner.get_mut(), self.node.as_ptr()) };
+        if let Err(e) = Error::check(ret) {
+            warn!("failed to finalize service client: {:?}", e);
         }
     }
 }
 
+fn nanos(duration: Duration) -> u64 {
+    duration.as_micros().saturating_mul(1000)
+}
+
+/// Running out of the timeout without any work is not an error for the spins
+fn ignore_timeout(ret: rcl_ret_t) -> Result<(), Error> {
+    match Error::check(ret) {
+        Err(e) if e.code() == Some(ReturnCode::Timeout) => Ok(()),
+        result => result,
+    }
+}
+
 mod util {
     use core::ffi::c_char;
 
-    pub fn create_null_terminated_string(src: &str, buffer: &mut [u8]) -> *const c_char {
+    use super::Error;
+
+    pub fn create_null_terminated_string(
+        src: &str,
+        buffer: &mut [u8],
+    ) -> Result<*const c_char, Error> {
         if !(buffer.len() > src.len()) {
-            defmt::panic!("too small buffer to allocate a nul terminated string")
+            return Err(Error::NameTooLong);
         }
 
         for (i, &b) in src.as_bytes().iter().enumerate() {
@@ -353,6 +954,6 @@ mod util {
         // null terminate
         buffer[src.len()] = 0;
 
-        buffer.as_ptr() as _
+        Ok(buffer.as_ptr() as _)
     }
 }
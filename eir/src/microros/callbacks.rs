@@ -0,0 +1,81 @@
+//! Storage for the Rust closures registered in the executor.
+//!
+//! rclc only passes an opaque context pointer to the callbacks, so the closures are moved into
+//! memory obtained from the rcutils allocator. Their address then stays stable even when the
+//! executor itself is moved. The allocations form an intrusive list owned by the executor.
+
+use core::{mem, ptr};
+
+use super::{Allocator, Error};
+
+/// Alignment guaranteed by newlib's `malloc`, which backs the default rcutils allocator
+const MAX_ALIGN: usize = 8;
+
+struct Header {
+    next: *mut Header,
+    drop_fn: unsafe fn(*mut Header),
+}
+
+#[repr(C)]
+struct Node<C> {
+    header: Header,
+    context: C,
+}
+
+pub(crate) struct CallbackList {
+    head: *mut Header,
+}
+
+impl CallbackList {
+    pub const fn new() -> Self {
+        Self {
+            head: ptr::null_mut(),
+        }
+    }
+
+    /// Moves `context` to the heap and returns a pointer to it,
+    /// which stays valid until the list is cleared.
+    pub fn push<C>(&mut self, allocator: &Allocator, context: C) -> Result<*mut C, Error> {
+        const {
+            assert!(
+                mem::align_of::<Node<C>>() <= MAX_ALIGN,
+                "callback context is over-aligned"
+            )
+        };
+
+        let node = allocator.allocate(mem::size_of::<Node<C>>()) as *mut Node<C>;
+        if node.is_null() {
+            return Err(Error::OutOfMemory);
+        }
+
+        unsafe {
+            node.write(Node {
+                header: Header {
+                    next: self.head,
+                    drop_fn: drop_node::<C>,
+                },
+                context,
+            });
+            self.head = node as *mut Header;
+
+            Ok(ptr::addr_of_mut!((*node).context))
+        }
+    }
+
+    /// Drops all the stored contexts and returns their memory to the allocator.
+    /// Must only be called once rclc no longer references the contexts.
+    pub fn clear(&mut self, allocator: &Allocator) {
+        while !self.head.is_null() {
+            let node = self.head;
+            unsafe {
+                self.head = (*node).next;
+                ((*node).drop_fn)(node);
+                allocator.deallocate(node as _);
+            }
+        }
+    }
+}
+
+unsafe fn drop_node<C>(header: *mut Header) {
+    ptr::drop_in_place(header as *mut Node<C>);
+}
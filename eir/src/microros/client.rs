@@ -0,0 +1,236 @@
+//! Service client resolving every call through a future.
+//!
+//! Responses are taken by the executor into a buffer owned by the client. rclc does not pass any
+//! context to client callbacks, so the registered clients are looked up by this buffer's address.
+
+use core::{
+    cell::RefCell,
+    future::{poll_fn, Future},
+    task::{Poll, Waker},
+};
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{with_timeout, Duration};
+use microros_sys::rmw_request_id_t;
+
+use super::{Error, RclNode, RclServiceClient};
+use crate::msg::{Message, Service};
+
+/// Maximum number of calls awaiting a response per client
+pub const MAX_PENDING_CALLS: usize = 4;
+/// Maximum number of typed clients registered in all executors
+pub const MAX_TYPED_CLIENTS: usize = 8;
+
+pub const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(1);
+
+enum CallState<R> {
+    Waiting(Option<Waker>),
+    Done(R),
+}
+
+struct PendingCall<R> {
+    sequence: i64,
+    state: CallState<R>,
+}
+
+pub struct TypedClient<'a, S: Service> {
+    inner: RclServiceClient<'a>,
+    response: S::Response,
+    pending: RefCell<[Option<PendingCall<S::Response>>; MAX_PENDING_CALLS]>,
+    timeout: Duration,
+}
+
+impl<'a, S> TypedClient<'a, S>
+where
+    S: Service,
+{
+    pub fn new(node: &'a RclNode<'a>, name: &str) -> Result<Self, Error> {
+        Ok(Self {
+            inner: RclServiceClient::new(node, unsafe { S::rosidl_type_support() }, name)?,
+            response: S::Response::default(),
+            pending: RefCell::new([const { None }; MAX_PENDING_CALLS]),
+            timeout: DEFAULT_CALL_TIMEOUT,
+        })
+    }
+
+    /// Sets how long calls wait for the response before failing with `Error::Timeout`
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Sends the request right away and returns a future resolving with the matching response.
+    /// Several calls may be outstanding at the same time, dropping the future abandons the call.
+    pub fn call(
+        &self,
+        request: &S::Request,
+    ) -> impl Future<Output = Result<S::Response, Error>> + '_ {
+        // the guard is created before returning, so a future dropped without being polled still
+        // releases its slot
+        let guard = self.send(request).map(|sequence| PendingGuard {
+            client: self,
+            sequence,
+        });
+
+        async move {
+            let guard = guard?;
+            let sequence = guard.sequence;
+
+            let response = poll_fn(|cx| self.poll_response(sequence, cx.waker()));
+            match with_timeout(self.timeout, response).await {
+                Ok(response) => Ok(response),
+                Err(_) => Err(Error::Timeout),
+            }
+        }
+    }
+
+    fn send(&self, request: &S::Request) -> Result<i64, Error> {
+        let mut pending = self.pending.borrow_mut();
+        let slot = pending
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(Error::TooManyPendingCalls)?;
+
+        let mut sequence = 0;
+        self.inner
+            .send_request(request.erased_ptr(), &mut sequence)?;
+        *slot = Some(PendingCall {
+            sequence,
+            state: CallState::Waiting(None),
+        });
+
+        Ok(sequence)
+    }
+
+    fn poll_response(&self, sequence: i64, waker: &Waker) -> Poll<S::Response> {
+        let mut pending = self.pending.borrow_mut();
+        let Some(slot) = pending
+            .iter_mut()
+            .find(|slot| matches!(slot, Some(call) if call.sequence == sequence))
+        else {
+            // the slot is only released by the guard, so this cannot happen
+            return Poll::Pending;
+        };
+
+        match slot.take() {
+            Some(PendingCall {
+                state: CallState::Done(response),
+                ..
+            }) => Poll::Ready(response),
+            _ => {
+                *slot = Some(PendingCall {
+                    sequence,
+                    state: CallState::Waiting(Some(waker.clone())),
+                });
+                Poll::Pending
+            }
+        }
+    }
+
+    /// Called from the executor once a response was taken into `self.response`
+    fn complete(&self, sequence: i64) {
+        let mut pending = self.pending.borrow_mut();
+        let Some(call) = pending
+            .iter_mut()
+            .flatten()
+            .find(|call| call.sequence == sequence)
+        else {
+            debug!("dropping response to abandoned call {}", sequence);
+            return;
+        };
+
+        let state = core::mem::replace(&mut call.state, CallState::Done(self.response.clone()));
+        if let CallState::Waiting(Some(waker)) = state {
+            waker.wake();
+        }
+    }
+
+    pub(super) fn as_rcl_client(&self) -> &RclServiceClient<'a> {
+        &self.inner
+    }
+
+    /// Buffer the executor takes the responses into
+    pub(super) fn response_ptr(&self) -> *mut core::ffi::c_void {
+        // Note(safety): the pointer targets the heap allocated message, not the wrapper
+        self.response.erased_ptr() as _
+    }
+}
+
+/// Releases the pending call slot, even when the call future is dropped before completion
+struct PendingGuard<'c, 'a, S: Service> {
+    client: &'c TypedClient<'a, S>,
+    sequence: i64,
+}
+
+impl<S: Service> Drop for PendingGuard<'_, '_, S> {
+    fn drop(&mut self) {
+        let mut pending = self.client.pending.borrow_mut();
+        for slot in pending.iter_mut() {
+            if matches!(slot, Some(call) if call.sequence == self.sequence) {
+                *slot = None;
+            }
+        }
+    }
+}
+
+/// Response buffer address paired with the address of the client owning it
+type Entry = Option<(usize, usize)>;
+
+static CLIENTS: Mutex<CriticalSectionRawMutex, RefCell<[Entry; MAX_TYPED_CLIENTS]>> =
+    Mutex::new(RefCell::new([None; MAX_TYPED_CLIENTS]));
+
+/// Keeps a client in the lookup table, owned by the executor the client was added to
+pub(super) struct Registration {
+    response: usize,
+}
+
+impl Registration {
+    pub fn new<S: Service>(client: &TypedClient<'_, S>) -> Result<Self, Error> {
+        let response = client.response_ptr() as usize;
+        let client = client as *const TypedClient<'_, S> as usize;
+
+        CLIENTS.lock(|clients| {
+            let mut clients = clients.borrow_mut();
+            let entry = clients
+                .iter_mut()
+                .find(|entry| entry.is_none())
+                .ok_or(Error::TooManyClients)?;
+            *entry = Some((response, client));
+            Ok(Self { response })
+        })
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        CLIENTS.lock(|clients| {
+            for entry in clients.borrow_mut().iter_mut() {
+                if matches!(entry, Some((response, _)) if *response == self.response) {
+                    *entry = None;
+                }
+            }
+        });
+    }
+}
+
+pub(super) unsafe extern "C" fn response_trampoline<S: Service>(
+    response: *const core::ffi::c_void,
+    request_header: *mut rmw_request_id_t,
+) {
+    if response.is_null() || request_header.is_null() {
+        return;
+    }
+
+    let client = CLIENTS.lock(|clients| {
+        clients
+            .borrow()
+            .iter()
+            .flatten()
+            .find(|(buffer, _)| *buffer == response as usize)
+            .map(|&(_, client)| client)
+    });
+
+    if let Some(client) = client {
+        let client = &*(client as *const TypedClient<'_, S>);
+        client.complete((*request_header).sequence_number);
+    }
+}
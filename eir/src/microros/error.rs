@@ -0,0 +1,180 @@
+use microros_sys::{
+    rcl_ret_t, rcutils_get_error_string, rcutils_reset_error, RCL_RET_ALREADY_INIT,
+    RCL_RET_ALREADY_SHUTDOWN, RCL_RET_BAD_ALLOC, RCL_RET_CLIENT_INVALID,
+    RCL_RET_CLIENT_TAKE_FAILED, RCL_RET_ERROR, RCL_RET_INVALID_ARGUMENT, RCL_RET_MISMATCHED_RMW_ID,
+    RCL_RET_NODE_INVALID, RCL_RET_NODE_INVALID_NAME, RCL_RET_NODE_INVALID_NAMESPACE,
+    RCL_RET_NOT_INIT, RCL_RET_OK, RCL_RET_PUBLISHER_INVALID, RCL_RET_SERVICE_INVALID,
+    RCL_RET_SERVICE_NAME_INVALID, RCL_RET_SERVICE_TAKE_FAILED, RCL_RET_SUBSCRIPTION_INVALID,
+    RCL_RET_SUBSCRIPTION_TAKE_FAILED, RCL_RET_TIMEOUT, RCL_RET_TIMER_CANCELED,
+    RCL_RET_TIMER_INVALID, RCL_RET_TOPIC_NAME_INVALID, RCL_RET_UNSUPPORTED, RCL_RET_WAIT_SET_EMPTY,
+    RCL_RET_WAIT_SET_FULL, RCL_RET_WAIT_SET_INVALID,
+};
+
+/// Maximum number of bytes kept from the rcl error string, the rest is truncated.
+/// Keeps `Error` small, as it is returned by value from every wrapper.
+pub const ERROR_MESSAGE_LEN: usize = 96;
+
+/// Errors returned by the rcl/rclc wrappers
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Error {
+    /// The underlying rcl/rclc/rmw call returned a non-OK code
+    Rcl {
+        code: ReturnCode,
+        message: ErrorMessage,
+    },
+    /// A node, topic or service name does not fit into the buffer used to nul terminate it
+    NameTooLong,
+    /// The allocator failed to provide memory for a callback
+    OutOfMemory,
+    /// No response to a service call arrived in time
+    Timeout,
+    /// The client already waits for `MAX_PENDING_CALLS` responses
+    TooManyPendingCalls,
+    /// `MAX_TYPED_CLIENTS` typed clients are already registered
+    TooManyClients,
+    /// `MAX_TIMERS` timers are already added to executors
+    TooManyTimers,
+}
+
+impl Error {
+    /// Returns the rcl return code, if the error originates in rcl
+    pub fn code(&self) -> Option<ReturnCode> {
+        match self {
+            Error::Rcl { code, .. } => Some(*code),
+            _ => None,
+        }
+    }
+
+    /// Converts the return value of an rcl call into a `Result`.
+    /// On failure, the thread local rcl error string is captured and reset.
+    pub(crate) fn check(ret: rcl_ret_t) -> Result<(), Error> {
+        if ret as u32 == RCL_RET_OK {
+            return Ok(());
+        }
+
+        let message = ErrorMessage::take();
+        let code = ReturnCode::from(ret);
+        trace!("rcl call failed: {:?} ({:?})", code, message);
+
+        Err(Error::Rcl { code, message })
+    }
+}
+
+/// Return codes of rcl and rmw (rmw shares the generic codes with rcl)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ReturnCode {
+    Error,
+    Timeout,
+    BadAlloc,
+    InvalidArgument,
+    Unsupported,
+    AlreadyInit,
+    NotInit,
+    MismatchedRmwId,
+    TopicNameInvalid,
+    ServiceNameInvalid,
+    AlreadyShutdown,
+    NodeInvalid,
+    NodeInvalidName,
+    NodeInvalidNamespace,
+    PublisherInvalid,
+    SubscriptionInvalid,
+    SubscriptionTakeFailed,
+    ClientInvalid,
+    ClientTakeFailed,
+    ServiceInvalid,
+    ServiceTakeFailed,
+    TimerInvalid,
+    TimerCanceled,
+    WaitSetInvalid,
+    WaitSetEmpty,
+    WaitSetFull,
+    Other(i32),
+}
+
+impl From<rcl_ret_t> for ReturnCode {
+    fn from(ret: rcl_ret_t) -> Self {
+        match ret as u32 {
+            RCL_RET_ERROR => ReturnCode::Error,
+            RCL_RET_TIMEOUT => ReturnCode::Timeout,
+            RCL_RET_BAD_ALLOC => ReturnCode::BadAlloc,
+            RCL_RET_INVALID_ARGUMENT => ReturnCode::InvalidArgument,
+            RCL_RET_UNSUPPORTED => ReturnCode::Unsupported,
+            RCL_RET_ALREADY_INIT => ReturnCode::AlreadyInit,
+            RCL_RET_NOT_INIT => ReturnCode::NotInit,
+            RCL_RET_MISMATCHED_RMW_ID => ReturnCode::MismatchedRmwId,
+            RCL_RET_TOPIC_NAME_INVALID => ReturnCode::TopicNameInvalid,
+            RCL_RET_SERVICE_NAME_INVALID => ReturnCode::ServiceNameInvalid,
+            RCL_RET_ALREADY_SHUTDOWN => ReturnCode::AlreadyShutdown,
+            RCL_RET_NODE_INVALID => ReturnCode::NodeInvalid,
+            RCL_RET_NODE_INVALID_NAME => ReturnCode::NodeInvalidName,
+            RCL_RET_NODE_INVALID_NAMESPACE => ReturnCode::NodeInvalidNamespace,
+            RCL_RET_PUBLISHER_INVALID => ReturnCode::PublisherInvalid,
+            RCL_RET_SUBSCRIPTION_INVALID => ReturnCode::SubscriptionInvalid,
+            RCL_RET_SUBSCRIPTION_TAKE_FAILED => ReturnCode::SubscriptionTakeFailed,
+            RCL_RET_CLIENT_INVALID => ReturnCode::ClientInvalid,
+            RCL_RET_CLIENT_TAKE_FAILED => ReturnCode::ClientTakeFailed,
+            RCL_RET_SERVICE_INVALID => ReturnCode::ServiceInvalid,
+            RCL_RET_SERVICE_TAKE_FAILED => ReturnCode::ServiceTakeFailed,
+            RCL_RET_TIMER_INVALID => ReturnCode::TimerInvalid,
+            RCL_RET_TIMER_CANCELED => ReturnCode::TimerCanceled,
+            RCL_RET_WAIT_SET_INVALID => ReturnCode::WaitSetInvalid,
+            RCL_RET_WAIT_SET_EMPTY => ReturnCode::WaitSetEmpty,
+            RCL_RET_WAIT_SET_FULL => ReturnCode::WaitSetFull,
+            _ => ReturnCode::Other(ret),
+        }
+    }
+}
+
+/// Copy of the rcl error string, truncated to `ERROR_MESSAGE_LEN` bytes
+#[derive(Clone, PartialEq, Eq)]
+pub struct ErrorMessage {
+    inner: [u8; ERROR_MESSAGE_LEN],
+    used: usize,
+}
+
+impl ErrorMessage {
+    /// Captures the current rcl error string and resets the error state
+    fn take() -> Self {
+        let mut message = Self {
+            inner: [0u8; ERROR_MESSAGE_LEN],
+            used: 0,
+        };
+
+        let raw = unsafe { rcutils_get_error_string() };
+        for &c in raw.str_.iter().take(ERROR_MESSAGE_LEN) {
+            if c == 0 {
+                break;
+            }
+            message.inner[message.used] = c as u8;
+            message.used += 1;
+        }
+        unsafe { rcutils_reset_error() };
+
+        message
+    }
+
+    pub fn as_str(&self) -> &str {
+        let bytes = &self.inner[..self.used];
+        // the message may have been truncated in the middle of a multi-byte character
+        match core::str::from_utf8(bytes) {
+            Ok(s) => s,
+            Err(e) => unsafe { core::str::from_utf8_unchecked(&bytes[..e.valid_up_to()]) },
+        }
+    }
+}
+
+impl core::fmt::Debug for ErrorMessage {
+    fn fmt(&self, f: &mut core::fmt::Formatter<'_>) -> core::fmt::Result {
+        core::fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+#[cfg(feature = "defmt")]
+impl defmt::Format for ErrorMessage {
+    fn format(&self, f: defmt::Formatter) {
+        defmt::write!(f, "{=str}", self.as_str())
+    }
+}
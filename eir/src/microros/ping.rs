@@ -0,0 +1,98 @@
+//! Waiting for the agent without blocking the other tasks of the executor for long.
+//!
+//! A single ping still blocks until the agent answers or the ping times out, as micro-ROS has no
+//! asynchronous ping, but the executor gets to run its other tasks between the attempts.
+
+use embassy_futures::yield_now;
+use embassy_time::{Duration, Timer};
+use microros_sys::{rmw_uros_ping_agent, RCL_RET_OK};
+
+/// How often and how long to ping the agent
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PingPolicy {
+    /// How long each ping waits for the answer
+    pub timeout: Duration,
+    /// Number of pings before giving up, `None` pings until the agent answers
+    pub attempts: Option<u32>,
+    /// Pause after the first failed ping
+    pub delay: Duration,
+    /// The pause doubles after every failed ping, up to this
+    pub max_delay: Duration,
+}
+
+impl PingPolicy {
+    /// `attempts` pings waiting `timeout` each, right after each other
+    pub const fn new(timeout: Duration, attempts: u32) -> Self {
+        Self {
+            timeout,
+            attempts: Some(attempts),
+            delay: Duration::from_ticks(0),
+            max_delay: Duration::from_ticks(0),
+        }
+    }
+
+    /// Pings until the agent answers
+    pub const fn forever(timeout: Duration) -> Self {
+        Self {
+            attempts: None,
+            ..Self::new(timeout, 0)
+        }
+    }
+
+    /// Pauses for `delay` after the first failed ping, doubling up to `max_delay`.
+    /// Pass the same value twice for a constant pause.
+    pub const fn with_backoff(self, delay: Duration, max_delay: Duration) -> Self {
+        Self {
+            delay,
+            max_delay,
+            ..self
+        }
+    }
+}
+
+impl Default for PingPolicy {
+    /// 10 pings of 1 s, like `rmw_uros_ping_agent(1000, 10)`
+    fn default() -> Self {
+        Self::new(Duration::from_secs(1), 10)
+    }
+}
+
+/// The agent did not answer any of the pings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct AgentUnavailable {
+    pub attempts: u32,
+}
+
+/// Waits for an agent on the host to answer a ping, following `policy`.
+/// The transport has to be running already.
+pub async fn wait_for_agent(policy: PingPolicy) -> Result<(), AgentUnavailable> {
+    info!("waiting for agent");
+    let mut attempts = 0;
+    let mut delay = policy.delay;
+    loop {
+        if ping_agent(policy.timeout) {
+            info!("agent answered after {} attempts", attempts + 1);
+            return Ok(());
+        }
+        attempts += 1;
+        if policy.attempts.is_some_and(|max| attempts >= max) {
+            warn!("agent did not answer {} pings", attempts);
+            return Err(AgentUnavailable { attempts });
+        }
+
+        if delay.as_ticks() == 0 {
+            yield_now().await;
+        } else {
+            Timer::after(delay).await;
+            delay = (delay * 2).min(policy.max_delay);
+        }
+    }
+}
+
+/// Pings the agent once, blocking for up to `timeout`
+pub(crate) fn ping_agent(timeout: Duration) -> bool {
+    let timeout_ms = timeout.as_millis().min(i32::MAX as u64) as i32;
+    unsafe { rmw_uros_ping_agent(timeout_ms, 1) as u32 == RCL_RET_OK }
+}
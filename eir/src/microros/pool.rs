@@ -0,0 +1,275 @@
+//! rcutils allocator over a fixed memory pool, bounding the memory micro-ROS can take.
+//!
+//! The pool keeps its bookkeeping at the start of the given memory and serves the allocations
+//! from the rest with a first fit linked list heap. rcutils does not pass the size to
+//! `deallocate`, so every allocation is preceded by a header holding it.
+
+use core::{
+    alloc::Layout,
+    cell::RefCell,
+    ffi::c_void,
+    mem,
+    ptr::{self, NonNull},
+};
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use linked_list_allocator::Heap;
+
+/// Alignment of the returned memory, like newlib's `malloc`
+const ALIGN: usize = 8;
+/// Room in front of every allocation for its size, keeping the allocation aligned
+const HEADER: usize = ALIGN;
+/// Smallest heap the linked list heap accepts, it asserts room for one free hole
+const MIN_HEAP: usize = 2 * mem::size_of::<usize>();
+
+/// Usage of a pool allocator
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct PoolStats {
+    /// Bytes available for allocations, including their headers
+    pub size: usize,
+    /// Bytes currently allocated
+    pub used: usize,
+    /// Most bytes allocated at the same time
+    pub peak: usize,
+    /// Allocations that failed for lack of memory
+    pub failed_allocations: usize,
+}
+
+struct PoolState {
+    heap: Heap,
+    peak: usize,
+    failed_allocations: usize,
+}
+
+pub(super) struct Pool {
+    state: Mutex<CriticalSectionRawMutex, RefCell<PoolState>>,
+}
+
+impl Pool {
+    /// Places the pool at the start of `memory`, the rest becomes the heap.
+    /// Returns `None` if `memory` is too small to hold the pool and the smallest heap.
+    pub fn new(memory: &'static mut [u8]) -> Option<&'static Pool> {
+        let offset = memory.as_ptr().align_offset(mem::align_of::<Pool>());
+        let heap_start = offset.checked_add(mem::size_of::<Pool>())?;
+        // the heap starts aligned, as `Pool` is at least aligned like `usize`
+        let heap_size = memory.len().checked_sub(heap_start)?;
+        if heap_size < MIN_HEAP {
+            return None;
+        }
+
+        let mut heap = Heap::empty();
+        unsafe {
+            let base = memory.as_mut_ptr();
+            heap.init(base.add(heap_start), heap_size);
+
+            let pool = base.add(offset) as *mut Pool;
+            pool.write(Pool {
+                state: Mutex::new(RefCell::new(PoolState {
+                    heap,
+                    peak: 0,
+                    failed_allocations: 0,
+                })),
+            });
+            Some(&*pool)
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.state.lock(|state| {
+            let state = state.borrow();
+            PoolStats {
+                size: state.heap.size(),
+                used: state.heap.used(),
+                peak: state.peak,
+                failed_allocations: state.failed_allocations,
+            }
+        })
+    }
+
+    fn allocate(&self, size: usize) -> *mut c_void {
+        let Some(layout) = layout(size) else {
+            return self.fail(size);
+        };
+
+        let block = self.state.lock(|state| {
+            let mut state = state.borrow_mut();
+            let block = state.heap.allocate_first_fit(layout).ok()?;
+            state.peak = state.peak.max(state.heap.used());
+            Some(block)
+        });
+
+        match block {
+            Some(block) => unsafe {
+                (block.as_ptr() as *mut usize).write(size);
+                block.as_ptr().add(HEADER) as _
+            },
+            None => self.fail(size),
+        }
+    }
+
+    /// # Safety
+    /// `pointer` has to be null or returned by this pool and not deallocated yet
+    unsafe fn deallocate(&self, pointer: *mut c_void) {
+        let Some((block, size)) = header(pointer) else {
+            return;
+        };
+        // the layout was valid when allocating
+        let layout = layout(size).unwrap();
+        self.state
+            .lock(|state| state.borrow_mut().heap.deallocate(block, layout));
+    }
+
+    /// # Safety
+    /// `pointer` has to be null or returned by this pool and not deallocated yet
+    unsafe fn reallocate(&self, pointer: *mut c_void, size: usize) -> *mut c_void {
+        let Some((_, old_size)) = header(pointer) else {
+            return self.allocate(size);
+        };
+
+        // on failure the old allocation stays valid, like with `realloc`
+        let new = self.allocate(size);
+        if !new.is_null() {
+            ptr::copy_nonoverlapping(pointer as *const u8, new as *mut u8, old_size.min(size));
+            self.deallocate(pointer);
+        }
+        new
+    }
+
+    fn zero_allocate(&self, count: usize, size: usize) -> *mut c_void {
+        let Some(size) = count.checked_mul(size) else {
+            return self.fail(usize::MAX);
+        };
+        let pointer = self.allocate(size);
+        if !pointer.is_null() {
+            unsafe { ptr::write_bytes(pointer as *mut u8, 0, size) };
+        }
+        pointer
+    }
+
+    fn fail(&self, size: usize) -> *mut c_void {
+        warn!("pool allocator failed to allocate {} bytes", size);
+        self.state
+            .lock(|state| state.borrow_mut().failed_allocations += 1);
+        ptr::null_mut()
+    }
+}
+
+fn layout(size: usize) -> Option<Layout> {
+    Layout::from_size_align(size.checked_add(HEADER)?, ALIGN).ok()
+}
+
+/// Start of the block holding `pointer` and the size it was allocated with
+unsafe fn header(pointer: *mut c_void) -> Option<(NonNull<u8>, usize)> {
+    if pointer.is_null() {
+        return None;
+    }
+    let block = (pointer as *mut u8).sub(HEADER);
+    Some((
+        NonNull::new_unchecked(block),
+        (block as *const usize).read(),
+    ))
+}
+
+pub(super) unsafe extern "C" fn allocate(size: usize, state: *mut c_void) -> *mut c_void {
+    (*(state as *const Pool)).allocate(size)
+}
+
+pub(super) unsafe extern "C" fn deallocate(pointer: *mut c_void, state: *mut c_void) {
+    (*(state as *const Pool)).deallocate(pointer)
+}
+
+pub(super) unsafe extern "C" fn reallocate(
+    pointer: *mut c_void,
+    size: usize,
+    state: *mut c_void,
+) -> *mut c_void {
+    (*(state as *const Pool)).reallocate(pointer, size)
+}
+
+pub(super) unsafe extern "C" fn zero_allocate(
+    count: usize,
+    size: usize,
+    state: *mut c_void,
+) -> *mut c_void {
+    (*(state as *const Pool)).zero_allocate(count, size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::Allocator;
+    use super::*;
+
+    fn memory(size: usize) -> &'static mut [u8] {
+        Box::leak(vec![0u8; size].into_boxed_slice())
+    }
+
+    #[test]
+    fn tracks_usage() {
+        let allocator = Allocator::from_pool(memory(1024)).unwrap();
+        let empty = allocator.pool_stats().unwrap();
+        assert_eq!(empty.used, 0);
+
+        let first = allocator.allocate(100);
+        let second = allocator.allocate(50);
+        assert!(!first.is_null() && !second.is_null());
+        assert_eq!(first as usize % ALIGN, 0);
+        let used = allocator.pool_stats().unwrap().used;
+        assert!(used >= 150 + 2 * HEADER);
+
+        unsafe { allocator.deallocate(first) };
+        let stats = allocator.pool_stats().unwrap();
+        assert!(stats.used < used);
+        assert_eq!(stats.peak, used);
+
+        unsafe { allocator.deallocate(second) };
+        assert_eq!(allocator.pool_stats().unwrap().used, 0);
+    }
+
+    #[test]
+    fn counts_failed_allocations() {
+        let allocator = Allocator::from_pool(memory(256)).unwrap();
+        assert!(allocator.allocate(1024).is_null());
+        assert!(!allocator.allocate(16).is_null());
+        assert_eq!(allocator.pool_stats().unwrap().failed_allocations, 1);
+    }
+
+    #[test]
+    fn reallocates_keeping_contents() {
+        let allocator = Allocator::from_pool(memory(1024)).unwrap();
+        unsafe {
+            let pointer = allocator.allocate(4) as *mut u8;
+            pointer.copy_from([1, 2, 3, 4].as_ptr(), 4);
+            let grown = allocator.reallocate(pointer as _, 64) as *mut u8;
+            assert_eq!(core::slice::from_raw_parts(grown, 4), [1, 2, 3, 4]);
+
+            // the old allocation stays valid when growing fails
+            assert!(allocator.reallocate(grown as _, 4096).is_null());
+            assert_eq!(core::slice::from_raw_parts(grown, 4), [1, 2, 3, 4]);
+            allocator.deallocate(grown as _);
+        }
+    }
+
+    #[test]
+    fn zero_allocates() {
+        let allocator = Allocator::from_pool(memory(1024)).unwrap();
+        let pool = allocator.pool.unwrap();
+        let dirty = pool.allocate(32);
+        unsafe {
+            ptr::write_bytes(dirty as *mut u8, 0xAA, 32);
+            pool.deallocate(dirty);
+        }
+
+        let zeroed = pool.zero_allocate(8, 4) as *const u8;
+        assert!(unsafe { core::slice::from_raw_parts(zeroed, 32) }
+            .iter()
+            .all(|&byte| byte == 0));
+        assert!(pool.zero_allocate(usize::MAX, 2).is_null());
+    }
+
+    #[test]
+    fn rejects_tiny_memory() {
+        assert!(Allocator::from_pool(memory(8)).is_none());
+        assert!(Allocator::from_pool(memory(mem::size_of::<Pool>() + 4)).is_none());
+    }
+}
@@ -0,0 +1,176 @@
+//! Quality of service settings of publishers and subscriptions.
+//!
+//! micro-ROS forwards reliability, durability and history to the agent, which applies them to
+//! the DDS entities it creates. Deadline and lifespan are passed on to rmw as well, but not every
+//! agent honours them.
+
+use embassy_time::Duration;
+use microros_sys::{
+    rmw_qos_durability_policy_e_RMW_QOS_POLICY_DURABILITY_TRANSIENT_LOCAL,
+    rmw_qos_durability_policy_e_RMW_QOS_POLICY_DURABILITY_VOLATILE,
+    rmw_qos_history_policy_e_RMW_QOS_POLICY_HISTORY_KEEP_ALL,
+    rmw_qos_history_policy_e_RMW_QOS_POLICY_HISTORY_KEEP_LAST,
+    rmw_qos_liveliness_policy_e_RMW_QOS_POLICY_LIVELINESS_SYSTEM_DEFAULT, rmw_qos_profile_t,
+    rmw_qos_reliability_policy_e_RMW_QOS_POLICY_RELIABILITY_BEST_EFFORT,
+    rmw_qos_reliability_policy_e_RMW_QOS_POLICY_RELIABILITY_RELIABLE, rmw_time_t,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Reliability {
+    /// Samples are resent until the reader acknowledged them
+    Reliable,
+    /// Samples are sent once, lost ones stay lost
+    BestEffort,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Durability {
+    /// Only readers present at the time a sample is written receive it
+    Volatile,
+    /// Late joining readers receive the kept samples
+    TransientLocal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum History {
+    /// Keeps the given number of the most recent samples
+    KeepLast(usize),
+    /// Keeps all samples, limited by the resources of the agent
+    KeepAll,
+}
+
+/// QoS of a publisher or subscription, built from one of the presets
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub struct QosProfile {
+    pub reliability: Reliability,
+    pub durability: Durability,
+    pub history: History,
+    /// Expected maximum time between samples, `None` leaves it unspecified
+    pub deadline: Option<Duration>,
+    /// Age after which a sample is no longer delivered, `None` leaves it unspecified
+    pub lifespan: Option<Duration>,
+}
+
+impl QosProfile {
+    /// Like `rmw_qos_profile_default`: reliable, volatile, keeping the last 10 samples
+    pub const fn default_profile() -> Self {
+        Self {
+            reliability: Reliability::Reliable,
+            durability: Durability::Volatile,
+            history: History::KeepLast(10),
+            deadline: None,
+            lifespan: None,
+        }
+    }
+
+    /// Like `rmw_qos_profile_sensor_data`: best effort, volatile, keeping the last 5 samples
+    pub const fn sensor_data() -> Self {
+        Self {
+            reliability: Reliability::BestEffort,
+            history: History::KeepLast(5),
+            ..Self::default_profile()
+        }
+    }
+
+    /// Like `rmw_qos_profile_services_default`: reliable, volatile, keeping the last 10 samples
+    pub const fn services_default() -> Self {
+        Self::default_profile()
+    }
+
+    pub const fn reliability(self, reliability: Reliability) -> Self {
+        Self {
+            reliability,
+            ..self
+        }
+    }
+
+    pub const fn best_effort(self) -> Self {
+        self.reliability(Reliability::BestEffort)
+    }
+
+    pub const fn reliable(self) -> Self {
+        self.reliability(Reliability::Reliable)
+    }
+
+    pub const fn durability(self, durability: Durability) -> Self {
+        Self { durability, ..self }
+    }
+
+    pub const fn history(self, history: History) -> Self {
+        Self { history, ..self }
+    }
+
+    /// Keeps the last `depth` samples
+    pub const fn keep_last(self, depth: usize) -> Self {
+        self.history(History::KeepLast(depth))
+    }
+
+    pub const fn deadline(self, deadline: Duration) -> Self {
+        Self {
+            deadline: Some(deadline),
+            ..self
+        }
+    }
+
+    pub const fn lifespan(self, lifespan: Duration) -> Self {
+        Self {
+            lifespan: Some(lifespan),
+            ..self
+        }
+    }
+
+    pub(super) fn to_rmw(self) -> rmw_qos_profile_t {
+        let (history, depth) = match self.history {
+            History::KeepLast(depth) => (
+                rmw_qos_history_policy_e_RMW_QOS_POLICY_HISTORY_KEEP_LAST,
+                depth,
+            ),
+            History::KeepAll => (rmw_qos_history_policy_e_RMW_QOS_POLICY_HISTORY_KEEP_ALL, 0),
+        };
+
+        rmw_qos_profile_t {
+            history,
+            depth,
+            reliability: match self.reliability {
+                Reliability::Reliable => {
+                    rmw_qos_reliability_policy_e_RMW_QOS_POLICY_RELIABILITY_RELIABLE
+                }
+                Reliability::BestEffort => {
+                    rmw_qos_reliability_policy_e_RMW_QOS_POLICY_RELIABILITY_BEST_EFFORT
+                }
+            },
+            durability: match self.durability {
+                Durability::Volatile => {
+                    rmw_qos_durability_policy_e_RMW_QOS_POLICY_DURABILITY_VOLATILE
+                }
+                Durability::TransientLocal => {
+                    rmw_qos_durability_policy_e_RMW_QOS_POLICY_DURABILITY_TRANSIENT_LOCAL
+                }
+            },
+            deadline: rmw_time(self.deadline),
+            lifespan: rmw_time(self.lifespan),
+            liveliness: rmw_qos_liveliness_policy_e_RMW_QOS_POLICY_LIVELINESS_SYSTEM_DEFAULT,
+            liveliness_lease_duration: rmw_time(None),
+            avoid_ros_namespace_conventions: false,
+        }
+    }
+}
+
+impl Default for QosProfile {
+    fn default() -> Self {
+        Self::default_profile()
+    }
+}
+
+/// `RMW_DURATION_UNSPECIFIED` is zero
+fn rmw_time(duration: Option<Duration>) -> rmw_time_t {
+    let micros = duration.map_or(0, |duration| duration.as_micros());
+    rmw_time_t {
+        sec: micros / 1_000_000,
+        nsec: micros % 1_000_000 * 1000,
+    }
+}
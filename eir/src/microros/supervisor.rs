@@ -0,0 +1,202 @@
+//! Reconnection to the agent, following the state machine of the micro-ROS reconnection example.
+//!
+//! The supervisor waits for the agent, creates the support and hands it to the application's
+//! session, which creates the node and the entities. While the session spins, the agent is pinged
+//! periodically. Once it stops answering, the session returns, everything it created is dropped
+//! together with the support, and the supervisor starts waiting for the agent again.
+
+use core::cell::Cell;
+use core::future::Future;
+
+use embassy_futures::select::select;
+use embassy_time::{Duration, Instant, Timer};
+use microros_sys::{
+    rcl_context_get_rmw_context, rmw_uros_set_context_entity_destroy_session_timeout,
+};
+use portable_atomic::{AtomicU8, Ordering};
+
+use super::ping::{ping_agent, wait_for_agent, PingPolicy};
+use super::{Allocator, Error, RclcExecutor, RclcSupport};
+
+/// How long a single ping waits for the agent's answer
+pub const PING_TIMEOUT: Duration = Duration::from_millis(100);
+pub const DEFAULT_PING_PERIOD: Duration = Duration::from_millis(500);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+#[repr(u8)]
+pub enum AgentState {
+    /// No agent answers the pings
+    WaitingAgent,
+    /// The agent answered, the support and the entities are being created
+    AgentAvailable,
+    /// The session is running
+    AgentConnected,
+    /// The agent got lost, the entities are being destroyed
+    AgentDisconnected,
+}
+
+impl AgentState {
+    fn from_u8(state: u8) -> Self {
+        match state {
+            1 => AgentState::AgentAvailable,
+            2 => AgentState::AgentConnected,
+            3 => AgentState::AgentDisconnected,
+            _ => AgentState::WaitingAgent,
+        }
+    }
+}
+
+/// Keeps the application connected to the agent. Can live in a `static`, so other tasks can
+/// follow the connection state, e.g. to show it on an LED.
+pub struct AgentSupervisor {
+    state: AtomicU8,
+    ping_period: Duration,
+}
+
+impl AgentSupervisor {
+    pub const fn new(ping_period: Duration) -> Self {
+        Self {
+            state: AtomicU8::new(AgentState::WaitingAgent as u8),
+            ping_period,
+        }
+    }
+
+    pub fn state(&self) -> AgentState {
+        AgentState::from_u8(self.state.load(Ordering::Relaxed))
+    }
+
+    fn set_state(&self, state: AgentState) {
+        if self.state.swap(state as u8, Ordering::Relaxed) != state as u8 {
+            info!("agent state: {:?}", state);
+        }
+    }
+
+    /// Runs `session` whenever the agent is available, until the session returns `Ok` without
+    /// the agent having been lost. A session that fails or loses the agent is started again
+    /// with a new support once the agent answers again.
+    ///
+    /// `session` is usually an `async fn(&Connection<'_>) -> Result<(), Error>` creating the
+    /// node and the entities, then spinning them with `Connection::spin`.
+    pub async fn run<S>(&self, allocator: &Allocator, mut session: S)
+    where
+        S: for<'s> Session<'s>,
+    {
+        loop {
+            self.set_state(AgentState::WaitingAgent);
+            let policy =
+                PingPolicy::forever(PING_TIMEOUT).with_backoff(self.ping_period, self.ping_period);
+            // never gives up
+            let _ = wait_for_agent(policy).await;
+
+            self.set_state(AgentState::AgentAvailable);
+            let support = match RclcSupport::new(allocator) {
+                Ok(support) => support,
+                Err(e) => {
+                    warn!("failed to create support: {:?}", e);
+                    continue;
+                }
+            };
+
+            let connection = Connection {
+                supervisor: self,
+                allocator,
+                support: &support,
+                last_ping: Cell::new(Instant::now()),
+                lost: Cell::new(false),
+            };
+            let result = session.start(&connection).await;
+            let lost = connection.lost.get();
+
+            match result {
+                Ok(()) if !lost => return,
+                Ok(()) => {}
+                Err(e) => warn!("session failed: {:?}", e),
+            }
+
+            self.set_state(AgentState::AgentDisconnected);
+            // the support is dropped here, after everything the session created
+        }
+    }
+}
+
+impl Default for AgentSupervisor {
+    fn default() -> Self {
+        Self::new(DEFAULT_PING_PERIOD)
+    }
+}
+
+/// Application code run while the agent is connected, implemented for functions taking the
+/// connection and returning a future
+pub trait Session<'s> {
+    type Future: Future<Output = Result<(), Error>> + 's;
+
+    fn start(&mut self, connection: &'s Connection<'s>) -> Self::Future;
+}
+
+impl<'s, F, Fut> Session<'s> for F
+where
+    F: FnMut(&'s Connection<'s>) -> Fut,
+    Fut: Future<Output = Result<(), Error>> + 's,
+{
+    type Future = Fut;
+
+    fn start(&mut self, connection: &'s Connection<'s>) -> Fut {
+        self(connection)
+    }
+}
+
+/// Connection to the agent handed to a session
+pub struct Connection<'a> {
+    supervisor: &'a AgentSupervisor,
+    allocator: &'a Allocator,
+    support: &'a RclcSupport<'a>,
+    last_ping: Cell<Instant>,
+    lost: Cell<bool>,
+}
+
+impl<'a> Connection<'a> {
+    pub fn allocator(&self) -> &'a Allocator {
+        self.allocator
+    }
+
+    pub fn support(&self) -> &'a RclcSupport<'a> {
+        self.support
+    }
+
+    /// Pings the agent if the ping period passed since the last ping.
+    /// Returns false once the agent got lost, the session should return then.
+    pub fn check(&self) -> bool {
+        if self.lost.get() {
+            return false;
+        }
+        self.supervisor.set_state(AgentState::AgentConnected);
+
+        if self.last_ping.get().elapsed() < self.supervisor.ping_period {
+            return true;
+        }
+        self.last_ping.set(Instant::now());
+        if ping_agent(PING_TIMEOUT) {
+            return true;
+        }
+
+        warn!("agent lost");
+        self.lost.set(true);
+        // the entities can not be destroyed on the agent anymore, don't wait for it
+        unsafe {
+            let context = rcl_context_get_rmw_context(self.support.context());
+            rmw_uros_set_context_entity_destroy_session_timeout(context, 0);
+        }
+        false
+    }
+
+    /// Runs `executor` until the agent gets lost
+    pub async fn spin(&self, executor: &mut RclcExecutor<'_>) {
+        let watch = async {
+            while self.check() {
+                Timer::after(self.supervisor.ping_period).await;
+            }
+        };
+        select(executor.run(), watch).await;
+    }
+}
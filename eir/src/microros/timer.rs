@@ -0,0 +1,167 @@
+//! Timers calling a Rust closure from the executor.
+//!
+//! rclc does not pass any context to timer callbacks, only the timer itself. Timers added to an
+//! executor are therefore looked up by their address, like the typed clients.
+
+use core::{
+    cell::{RefCell, UnsafeCell},
+    marker::PhantomData,
+    mem::MaybeUninit,
+};
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::Duration;
+use microros_sys::{
+    rcl_timer_cancel, rcl_timer_exchange_period, rcl_timer_fini, rcl_timer_get_period,
+    rcl_timer_is_canceled, rcl_timer_reset, rcl_timer_t, rclc_timer_init_default,
+};
+
+use super::{nanos, Error, RclcSupport};
+
+/// Maximum number of timers added to all executors
+pub const MAX_TIMERS: usize = 8;
+
+/// Calls `callback` every `period` while it is added to a spinning executor.
+/// The callback gets the time passed since its previous call.
+pub struct RclTimer<'a, F> {
+    inner: UnsafeCell<rcl_timer_t>,
+    callback: RefCell<F>,
+    _support: PhantomData<&'a ()>,
+}
+
+impl<'a, F> RclTimer<'a, F>
+where
+    F: FnMut(Duration),
+{
+    pub fn new(support: &'a RclcSupport<'_>, period: Duration, callback: F) -> Result<Self, Error> {
+        let mut raw: MaybeUninit<rcl_timer_t> = MaybeUninit::uninit();
+        // Note(safety): rclc only uses the clock and the context of the support
+        Error::check(unsafe {
+            rclc_timer_init_default(
+                raw.as_mut_ptr(),
+                support.as_ptr(),
+                nanos(period),
+                Some(timer_trampoline::<F>),
+            )
+        })?;
+
+        Ok(Self {
+            inner: UnsafeCell::new(unsafe { raw.assume_init() }),
+            callback: RefCell::new(callback),
+            _support: PhantomData,
+        })
+    }
+
+    fn call(&self, elapsed: Duration) {
+        match self.callback.try_borrow_mut() {
+            Ok(mut callback) => callback(elapsed),
+            Err(_) => warn!("timer callback called recursively"),
+        }
+    }
+}
+
+impl<F> RclTimer<'_, F> {
+    /// Restarts the period from now, also resuming a canceled timer
+    pub fn reset(&self) -> Result<(), Error> {
+        Error::check(unsafe { rcl_timer_reset(self.as_ptr()) })
+    }
+
+    /// Stops calling the callback until the timer is reset
+    pub fn cancel(&self) -> Result<(), Error> {
+        Error::check(unsafe { rcl_timer_cancel(self.as_ptr()) })
+    }
+
+    pub fn is_canceled(&self) -> Result<bool, Error> {
+        let mut canceled = false;
+        Error::check(unsafe { rcl_timer_is_canceled(self.as_ptr(), &mut canceled) })?;
+        Ok(canceled)
+    }
+
+    pub fn period(&self) -> Result<Duration, Error> {
+        let mut period = 0;
+        Error::check(unsafe { rcl_timer_get_period(self.as_ptr(), &mut period) })?;
+        Ok(Duration::from_nanos(period.max(0) as u64))
+    }
+
+    /// Sets a new period, returning the previous one. It takes effect after the next call,
+    /// `reset` applies it right away.
+    pub fn change_period(&self, period: Duration) -> Result<Duration, Error> {
+        let mut previous = 0;
+        Error::check(unsafe {
+            rcl_timer_exchange_period(self.as_ptr(), nanos(period) as i64, &mut previous)
+        })?;
+        Ok(Duration::from_nanos(previous.max(0) as u64))
+    }
+
+    pub(super) fn as_ptr(&self) -> *mut rcl_timer_t {
+        self.inner.get()
+    }
+}
+
+impl<F> Drop for RclTimer<'_, F> {
+    fn drop(&mut self) {
+        if let Err(e) = Error::check(unsafe { rcl_timer_fini(self.inner.get_mut()) }) {
+            warn!("failed to finalize timer: {:?}", e);
+        }
+    }
+}
+
+/// Address of the rcl timer paired with the address of the wrapper owning it
+type Entry = Option<(usize, usize)>;
+
+static TIMERS: Mutex<CriticalSectionRawMutex, RefCell<[Entry; MAX_TIMERS]>> =
+    Mutex::new(RefCell::new([None; MAX_TIMERS]));
+
+/// Keeps a timer in the lookup table, owned by the executor the timer was added to
+pub(super) struct Registration {
+    timer: usize,
+}
+
+impl Registration {
+    pub fn new<F>(timer: &RclTimer<'_, F>) -> Result<Self, Error> {
+        let inner = timer.as_ptr() as usize;
+        let timer = timer as *const RclTimer<'_, F> as usize;
+
+        TIMERS.lock(|timers| {
+            let mut timers = timers.borrow_mut();
+            let entry = timers
+                .iter_mut()
+                .find(|entry| entry.is_none())
+                .ok_or(Error::TooManyTimers)?;
+            *entry = Some((inner, timer));
+            Ok(Self { timer: inner })
+        })
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        TIMERS.lock(|timers| {
+            for entry in timers.borrow_mut().iter_mut() {
+                if matches!(entry, Some((timer, _)) if *timer == self.timer) {
+                    *entry = None;
+                }
+            }
+        });
+    }
+}
+
+unsafe extern "C" fn timer_trampoline<F>(timer: *mut rcl_timer_t, last_call_time: i64)
+where
+    F: FnMut(Duration),
+{
+    let wrapper = TIMERS.lock(|timers| {
+        timers
+            .borrow()
+            .iter()
+            .flatten()
+            .find(|(inner, _)| *inner == timer as usize)
+            .map(|&(_, wrapper)| wrapper)
+    });
+
+    // timers are only called by the executor they were registered with
+    if let Some(wrapper) = wrapper {
+        let timer = &*(wrapper as *const RclTimer<'_, F>);
+        timer.call(Duration::from_nanos(last_call_time.max(0) as u64));
+    }
+}
@@ -0,0 +1,84 @@
+//! Conditions deciding whether a spin of the executor processes the ready handles.
+
+use core::ffi::{c_uint, c_void};
+
+use microros_sys::rclc_executor_handle_t;
+
+/// Condition checked by every spin before the handles are processed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum Trigger {
+    /// Any handle has new data, the default
+    Any,
+    /// All handles have new data, e.g. to process the readings of several sensors together
+    All,
+    /// Every spin processes the handles, even without new data
+    Always,
+    /// The handle added as the given one (counting from 0) has new data
+    One(usize),
+}
+
+/// Handles of the executor passed to a custom trigger, in the order they were added
+pub struct ReadyHandles<'a> {
+    handles: &'a [rclc_executor_handle_t],
+}
+
+impl ReadyHandles<'_> {
+    pub fn len(&self) -> usize {
+        self.handles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.handles.is_empty()
+    }
+
+    /// Whether the handle added as `index` has new data
+    pub fn is_ready(&self, index: usize) -> bool {
+        self.handles
+            .get(index)
+            .is_some_and(|handle| handle.data_available)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = bool> + '_ {
+        self.handles.iter().map(|handle| handle.data_available)
+    }
+}
+
+/// # Safety
+/// `handles` has to point to `size` handles, of which the added ones come first
+unsafe fn ready_handles<'a>(
+    handles: *mut rclc_executor_handle_t,
+    size: c_uint,
+) -> ReadyHandles<'a> {
+    let handles =
+        core::slice::from_raw_parts(handles as *const rclc_executor_handle_t, size as usize);
+    // the array has room for `number_of_handles`, the unused ones follow the added ones
+    let added = handles
+        .iter()
+        .position(|handle| !handle.initialized)
+        .unwrap_or(handles.len());
+    ReadyHandles {
+        handles: &handles[..added],
+    }
+}
+
+/// Trigger of `Trigger::One`, the index is passed as the object pointer
+pub(super) unsafe extern "C" fn one_trampoline(
+    handles: *mut rclc_executor_handle_t,
+    size: c_uint,
+    index: *mut c_void,
+) -> bool {
+    ready_handles(handles, size).is_ready(index as usize)
+}
+
+pub(super) unsafe extern "C" fn closure_trampoline<F>(
+    handles: *mut rclc_executor_handle_t,
+    size: c_uint,
+    context: *mut c_void,
+) -> bool
+where
+    F: FnMut(&ReadyHandles<'_>) -> bool,
+{
+    let predicate = &mut *(context as *mut F);
+    predicate(&ready_handles(handles, size))
+}
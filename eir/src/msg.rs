@@ -1,6 +1,11 @@
-use core::ops::{Deref, DerefMut};
+use microros_sys::{rosidl_message_type_support_t, rosidl_service_type_support_t};
 
-use microros_sys::rosidl_message_type_support_t;
+mod convert;
+
+/// Maximum length in bytes of the strings in owned messages
+pub const STRING_CAPACITY: usize = 64;
+/// Maximum number of elements of the sequences in owned messages
+pub const SEQUENCE_CAPACITY: usize = 16;
 
 // TODO: to achieve "safe" api, these methods should not be available to the user
 pub trait Message {
@@ -9,8 +14,65 @@ pub trait Message {
     fn erased_mut_ptr(&mut self) -> *mut core::ffi::c_void;
 }
 
+/// C structure of a message holding no pointers, i.e. no strings or sequences. It can be
+/// published straight from the stack or a `static`, without a wrapper allocated by rosidl.
+/// Implemented for the messages in the `plain` modules, e.g. `msg::geometry_msgs::plain::Twist`.
+///
+/// # Safety
+/// The type support has to describe the type. Raw pointers are neither `Send` nor `Sync`, so
+/// structures holding one are rejected at compile time.
+pub unsafe trait PlainMessage: Copy + Send + Sync + 'static {
+    /// # Safety
+    /// Calls into the C type support library
+    unsafe fn rosidl_type_support() -> *const rosidl_message_type_support_t;
+}
+
+impl<T: PlainMessage> Message for T {
+    unsafe fn rosidl_type_support() -> *const rosidl_message_type_support_t {
+        <T as PlainMessage>::rosidl_type_support()
+    }
+
+    fn erased_ptr(&self) -> *const core::ffi::c_void {
+        self as *const T as _
+    }
+
+    fn erased_mut_ptr(&mut self) -> *mut core::ffi::c_void {
+        self as *mut T as _
+    }
+}
+
+/// Pairs the request and response messages of a service with its type support
+pub trait Service {
+    type Request: Message + Default;
+    type Response: Message + Default + Clone;
+
+    unsafe fn rosidl_type_support() -> *const rosidl_service_type_support_t;
+}
+
+/// Plain Rust representation of a message, holding its strings and sequences inline
+/// instead of in memory managed by the rosidl runtime
+pub trait OwnedMessage: Sized {
+    /// The C structure generated by rosidl
+    type Raw;
+
+    fn from_raw(raw: &Self::Raw) -> Result<Self, ConversionError>;
+    /// Overwrites `raw`, resizing its strings and sequences through the rosidl runtime
+    fn write_raw(&self, raw: &mut Self::Raw) -> Result<(), ConversionError>;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum ConversionError {
+    /// A string or sequence is longer than `STRING_CAPACITY` or `SEQUENCE_CAPACITY`
+    CapacityExceeded,
+    /// A received string is not valid UTF-8
+    InvalidUtf8,
+    /// The rosidl runtime failed to allocate a string or sequence
+    OutOfMemory,
+}
+
 macro_rules! generate_msg_wrapper {
-    ($wrapper:ident, $msg:path, $create_fn: path, $fini_fn: path, $rosidl_fn: path) => {
+    ($wrapper:ident, $msg:path, $create_fn: path, $destroy_fn: path, $copy_fn: path, $rosidl_fn: path) => {
         pub struct $wrapper {
             inner: *mut $msg,
         }
@@ -23,20 +85,30 @@ macro_rules! generate_msg_wrapper {
             }
         }
 
+        impl Clone for $wrapper {
+            fn clone(&self) -> Self {
+                let copy = Self::default();
+                if !unsafe { $copy_fn(self.inner, copy.inner) } {
+                    panic!("failed to copy message");
+                }
+                copy
+            }
+        }
+
         impl Drop for $wrapper {
             fn drop(&mut self) {
-                unsafe { $fini_fn(self.inner) }
+                unsafe { $destroy_fn(self.inner) }
             }
         }
 
-        impl Deref for $wrapper {
+        impl core::ops::Deref for $wrapper {
             type Target = $msg;
             fn deref(&self) -> &Self::Target {
                 unsafe { &*self.inner }
             }
         }
 
-        impl DerefMut for $wrapper {
+        impl core::ops::DerefMut for $wrapper {
             fn deref_mut(&mut self) -> &mut Self::Target {
                 unsafe { &mut *self.inner }
             }
@@ -58,18 +130,57 @@ macro_rules! generate_msg_wrapper {
     };
 }
 
-generate_msg_wrapper!(
-    Empty,
-    microros_sys::std_msgs__msg__Empty,
-    microros_sys::std_msgs__msg__Empty__create,
-    microros_sys::std_msgs__msg__Empty__fini,
-    microros_sys::rosidl_typesupport_c__get_message_type_support_handle__std_msgs__msg__Empty
-);
-
-generate_msg_wrapper!(
-    BatteryState,
-    microros_sys::sensor_msgs__msg__BatteryState,
-    microros_sys::sensor_msgs__msg__BatteryState__create,
-    microros_sys::sensor_msgs__msg__BatteryState__fini,
-    microros_sys::rosidl_typesupport_c__get_message_type_support_handle__sensor_msgs__msg__BatteryState
-);
+macro_rules! generate_plain_message {
+    ($alias:ident, $msg:path, $rosidl_fn: path) => {
+        pub type $alias = $msg;
+
+        unsafe impl crate::msg::PlainMessage for $msg {
+            unsafe fn rosidl_type_support() -> *const microros_sys::rosidl_message_type_support_t {
+                $rosidl_fn()
+            }
+        }
+    };
+}
+
+macro_rules! generate_srv_wrapper {
+    ($wrapper:ident, $request:ident, $response:ident, $rosidl_fn: path) => {
+        pub struct $wrapper;
+
+        impl crate::msg::Service for $wrapper {
+            type Request = $request;
+            type Response = $response;
+
+            unsafe fn rosidl_type_support() -> *const microros_sys::rosidl_service_type_support_t {
+                $rosidl_fn()
+            }
+        }
+    };
+}
+
+macro_rules! generate_owned_conversions {
+    ($wrapper:ident) => {
+        impl TryFrom<&owned::$wrapper> for $wrapper {
+            type Error = crate::msg::ConversionError;
+
+            fn try_from(message: &owned::$wrapper) -> Result<Self, Self::Error> {
+                let mut wrapper = Self::default();
+                crate::msg::OwnedMessage::write_raw(message, &mut wrapper)?;
+                Ok(wrapper)
+            }
+        }
+
+        impl TryFrom<&$wrapper> for owned::$wrapper {
+            type Error = crate::msg::ConversionError;
+
+            fn try_from(message: &$wrapper) -> Result<Self, Self::Error> {
+                crate::msg::OwnedMessage::from_raw(&**message)
+            }
+        }
+    };
+}
+
+// Wrappers for all the messages and services found in the bindings, grouped by package,
+// e.g. `msg::std_msgs::Int32` or `msg::std_srvs::SetBool`, the owned messages,
+// e.g. `msg::sensor_msgs::owned::BatteryState`, and the plain ones,
+// e.g. `msg::geometry_msgs::plain::Twist`. Generated by `build.rs`.
+include!(concat!(env!("OUT_DIR"), "/messages.rs"));
@@ -0,0 +1,77 @@
+//! Helpers used by the generated owned messages to access rosidl strings and sequences.
+
+use core::{ffi::c_char, slice};
+
+use microros_sys::{rosidl_runtime_c__String, rosidl_runtime_c__String__assignn};
+
+use super::ConversionError;
+
+/// Elements of a sequence, which may have a null `data` pointer while empty
+pub(crate) unsafe fn slice<'a, T>(data: *const T, size: usize) -> &'a [T] {
+    if data.is_null() || size == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, size)
+    }
+}
+
+pub(crate) unsafe fn slice_mut<'a, T>(data: *mut T, size: usize) -> &'a mut [T] {
+    if data.is_null() || size == 0 {
+        &mut []
+    } else {
+        slice::from_raw_parts_mut(data, size)
+    }
+}
+
+/// Reallocates `sequence` to hold `size` default initialized elements
+pub(crate) unsafe fn resize_sequence<S>(
+    sequence: &mut S,
+    size: usize,
+    fini: unsafe extern "C" fn(*mut S),
+    init: unsafe extern "C" fn(*mut S, usize) -> bool,
+) -> Result<(), ConversionError> {
+    fini(sequence);
+    if init(sequence, size) {
+        Ok(())
+    } else {
+        Err(ConversionError::OutOfMemory)
+    }
+}
+
+pub(crate) fn string_from_raw<const N: usize>(
+    raw: &rosidl_runtime_c__String,
+) -> Result<heapless::String<N>, ConversionError> {
+    let bytes = unsafe { slice(raw.data as *const u8, raw.size) };
+    let value = core::str::from_utf8(bytes).map_err(|_| ConversionError::InvalidUtf8)?;
+
+    let mut string = heapless::String::new();
+    string
+        .push_str(value)
+        .map_err(|_| ConversionError::CapacityExceeded)?;
+    Ok(string)
+}
+
+pub(crate) fn string_to_raw(
+    value: &str,
+    raw: &mut rosidl_runtime_c__String,
+) -> Result<(), ConversionError> {
+    if unsafe {
+        rosidl_runtime_c__String__assignn(raw, value.as_ptr() as *const c_char, value.len())
+    } {
+        Ok(())
+    } else {
+        Err(ConversionError::OutOfMemory)
+    }
+}
+
+pub(crate) fn collect<T, const N: usize>(
+    elements: impl Iterator<Item = Result<T, ConversionError>>,
+) -> Result<heapless::Vec<T, N>, ConversionError> {
+    let mut collected = heapless::Vec::new();
+    for element in elements {
+        collected
+            .push(element?)
+            .map_err(|_| ConversionError::CapacityExceeded)?;
+    }
+    Ok(collected)
+}
@@ -0,0 +1,245 @@
+//! Safe access to the strings and sequences of rosidl messages.
+//!
+//! The traits are implemented directly on the C structures, so they can be used on the fields of
+//! the message wrappers, e.g. `state.location.set("left")`. Memory comes from the rcutils default
+//! allocator, the one rosidl finalizes the messages with. Taking an `Allocator` would allow a pool
+//! allocator, whose memory rosidl would then hand to `free`.
+
+use core::{mem, ptr, slice, str::Utf8Error};
+
+use microros_sys::{
+    rosidl_runtime_c__String, rosidl_runtime_c__String__Sequence,
+    rosidl_runtime_c__boolean__Sequence, rosidl_runtime_c__char__Sequence,
+    rosidl_runtime_c__double__Sequence, rosidl_runtime_c__float__Sequence,
+    rosidl_runtime_c__int16__Sequence, rosidl_runtime_c__int32__Sequence,
+    rosidl_runtime_c__int64__Sequence, rosidl_runtime_c__int8__Sequence,
+    rosidl_runtime_c__octet__Sequence, rosidl_runtime_c__uint16__Sequence,
+    rosidl_runtime_c__uint32__Sequence, rosidl_runtime_c__uint64__Sequence,
+    rosidl_runtime_c__uint8__Sequence, rosidl_runtime_c__wchar__Sequence,
+};
+
+use crate::microros::{Allocator, Error};
+
+/// `rosidl_runtime_c__String`, a null terminated string with its length and capacity
+pub trait RosString {
+    fn as_bytes(&self) -> &[u8];
+
+    fn as_str(&self) -> Result<&str, Utf8Error> {
+        core::str::from_utf8(self.as_bytes())
+    }
+
+    /// Replaces the contents, growing the buffer when `value` does not fit
+    fn set(&mut self, value: &str) -> Result<(), Error>;
+}
+
+impl RosString for rosidl_runtime_c__String {
+    fn as_bytes(&self) -> &[u8] {
+        unsafe { raw_slice(self.data as *const u8, self.size) }
+    }
+
+    fn set(&mut self, value: &str) -> Result<(), Error> {
+        let allocator = Allocator::default();
+        // the capacity includes the null terminator
+        let capacity = value.len().checked_add(1).ok_or(Error::OutOfMemory)?;
+        if capacity > self.capacity {
+            let data = unsafe { allocator.reallocate(self.data as _, capacity) };
+            if data.is_null() {
+                return Err(Error::OutOfMemory);
+            }
+            self.data = data as _;
+            self.capacity = capacity;
+        }
+
+        unsafe {
+            ptr::copy_nonoverlapping(value.as_ptr(), self.data as *mut u8, value.len());
+            *self.data.add(value.len()) = 0;
+        }
+        self.size = value.len();
+
+        Ok(())
+    }
+}
+
+/// Element of a sequence, initialized and finalized as the sequence is resized.
+/// `allocator` is always the rcutils default allocator.
+pub trait Element: Sized {
+    /// Writes an empty element to uninitialized memory
+    #[doc(hidden)]
+    unsafe fn init(element: *mut Self, allocator: &Allocator) -> Result<(), Error>;
+    /// Frees the memory owned by the element
+    #[doc(hidden)]
+    unsafe fn fini(element: *mut Self, allocator: &Allocator);
+}
+
+macro_rules! impl_primitive_element {
+    ($($primitive:ty),*) => {
+        $(
+            impl Element for $primitive {
+                unsafe fn init(element: *mut Self, _allocator: &Allocator) -> Result<(), Error> {
+                    element.write(Default::default());
+                    Ok(())
+                }
+
+                unsafe fn fini(_element: *mut Self, _allocator: &Allocator) {}
+            }
+        )*
+    };
+}
+
+impl_primitive_element!(bool, u8, i8, u16, i16, u32, i32, u64, i64, f32, f64);
+
+impl Element for rosidl_runtime_c__String {
+    /// Matches `rosidl_runtime_c__String__init`, which allocates just the null terminator
+    unsafe fn init(element: *mut Self, allocator: &Allocator) -> Result<(), Error> {
+        let data = allocator.allocate(1) as *mut core::ffi::c_char;
+        if data.is_null() {
+            return Err(Error::OutOfMemory);
+        }
+        *data = 0;
+
+        element.write(rosidl_runtime_c__String {
+            data,
+            size: 0,
+            capacity: 1,
+        });
+        Ok(())
+    }
+
+    /// Leaves the element empty like `rosidl_runtime_c__String__fini`. Sequences finalize every
+    /// element up to their capacity, so an element removed by shrinking is finalized again.
+    unsafe fn fini(element: *mut Self, allocator: &Allocator) {
+        if !(*element).data.is_null() {
+            allocator.deallocate((*element).data as _);
+        }
+        element.write(rosidl_runtime_c__String {
+            data: ptr::null_mut(),
+            size: 0,
+            capacity: 0,
+        });
+    }
+}
+
+/// rosidl sequence of `Element`s, e.g. `rosidl_runtime_c__float__Sequence`
+pub trait Sequence {
+    type Element: Element;
+
+    fn as_slice(&self) -> &[Self::Element];
+    fn as_mut_slice(&mut self) -> &mut [Self::Element];
+
+    fn len(&self) -> usize {
+        self.as_slice().len()
+    }
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn iter(&self) -> slice::Iter<'_, Self::Element> {
+        self.as_slice().iter()
+    }
+
+    fn iter_mut(&mut self) -> slice::IterMut<'_, Self::Element> {
+        self.as_mut_slice().iter_mut()
+    }
+
+    /// Changes the number of elements. New elements are zeroed or empty strings,
+    /// the memory of removed elements is freed.
+    fn resize(&mut self, size: usize) -> Result<(), Error>;
+}
+
+macro_rules! impl_sequence {
+    ($($sequence:ty => $element:ty),* $(,)?) => {
+        $(
+            impl Sequence for $sequence {
+                type Element = $element;
+
+                fn as_slice(&self) -> &[Self::Element] {
+                    unsafe { raw_slice(self.data, self.size) }
+                }
+
+                fn as_mut_slice(&mut self) -> &mut [Self::Element] {
+                    if self.data.is_null() || self.size == 0 {
+                        &mut []
+                    } else {
+                        unsafe { slice::from_raw_parts_mut(self.data, self.size) }
+                    }
+                }
+
+                fn resize(&mut self, size: usize) -> Result<(), Error> {
+                    unsafe {
+                        resize(
+                            &mut self.data,
+                            &mut self.size,
+                            &mut self.capacity,
+                            size,
+                            &Allocator::default(),
+                        )
+                    }
+                }
+            }
+        )*
+    };
+}
+
+impl_sequence!(
+    rosidl_runtime_c__boolean__Sequence => bool,
+    rosidl_runtime_c__octet__Sequence => u8,
+    rosidl_runtime_c__char__Sequence => i8,
+    rosidl_runtime_c__wchar__Sequence => u16,
+    rosidl_runtime_c__uint8__Sequence => u8,
+    rosidl_runtime_c__int8__Sequence => i8,
+    rosidl_runtime_c__uint16__Sequence => u16,
+    rosidl_runtime_c__int16__Sequence => i16,
+    rosidl_runtime_c__uint32__Sequence => u32,
+    rosidl_runtime_c__int32__Sequence => i32,
+    rosidl_runtime_c__uint64__Sequence => u64,
+    rosidl_runtime_c__int64__Sequence => i64,
+    rosidl_runtime_c__float__Sequence => f32,
+    rosidl_runtime_c__double__Sequence => f64,
+    rosidl_runtime_c__String__Sequence => rosidl_runtime_c__String,
+);
+
+unsafe fn raw_slice<'a, T>(data: *const T, size: usize) -> &'a [T] {
+    if data.is_null() || size == 0 {
+        &[]
+    } else {
+        slice::from_raw_parts(data, size)
+    }
+}
+
+/// Shared by all the sequences, which only differ in their element type.
+/// The buffer only grows, shrinking keeps the capacity for later use. rosidl finalizes the
+/// elements up to the capacity, so the ones past the size are always finalized or zeroed.
+unsafe fn resize<T: Element>(
+    data: &mut *mut T,
+    size: &mut usize,
+    capacity: &mut usize,
+    new_size: usize,
+    allocator: &Allocator,
+) -> Result<(), Error> {
+    while *size > new_size {
+        *size -= 1;
+        T::fini(data.add(*size), allocator);
+    }
+
+    if new_size > *capacity {
+        let bytes = new_size
+            .checked_mul(mem::size_of::<T>())
+            .ok_or(Error::OutOfMemory)?;
+        let grown = allocator.reallocate(*data as _, bytes) as *mut T;
+        if grown.is_null() {
+            return Err(Error::OutOfMemory);
+        }
+        // zeroed elements are empty for rosidl, even if initializing them fails below
+        ptr::write_bytes(grown.add(*capacity), 0, new_size - *capacity);
+        *data = grown;
+        *capacity = new_size;
+    }
+
+    while *size < new_size {
+        T::init(data.add(*size), allocator)?;
+        *size += 1;
+    }
+
+    Ok(())
+}
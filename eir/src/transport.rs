@@ -1,208 +1,249 @@
-use embassy_executor::Spawner;
-use embassy_rp::{
-    bind_interrupts,
-    usb::{Driver, InterruptHandler},
+//! Transports carrying the XRCE messages between micro-ROS and the agent.
+//!
+//! micro-ROS calls the transport from thread mode and blocks until it returns, so the transports
+//! wait for the async drivers running in interrupt executors by sleeping the core.
+
+use core::{
+    future::Future,
+    pin::pin,
+    task::{Context, Poll, Waker},
 };
-use embassy_sync::{
-    blocking_mutex::raw::CriticalSectionRawMutex,
-    channel::{self, Channel},
-};
-use embassy_time::{Duration, Instant};
-use embassy_usb::class::cdc_acm::{self, CdcAcmClass, State};
-use microros_sys::{rmw_uros_set_custom_transport, uxrCustomTransport};
-use static_cell::StaticCell;
 
-bind_interrupts!(struct Irqs {
-    USBCTRL_IRQ => InterruptHandler<embassy_rp::peripherals::USB>;
-});
-
-pub type MyUsbDriver = Driver<'static, embassy_rp::peripherals::USB>;
-pub type MyUsbDevice = embassy_usb::UsbDevice<'static, MyUsbDriver>;
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{with_timeout, Duration};
+use microros_sys::{rmw_uros_set_custom_transport, uxrCustomTransport};
 
-pub fn init_rmw_transport() {
-    // TODO: we could check that this runs in thread mode
-    unsafe {
-        rmw_uros_set_custom_transport(
-            true,
-            core::ptr::null_mut(),
-            Some(transport_open),
-            Some(transport_close),
-            Some(transport_write),
-            Some(transport_read),
-        )
-    };
+#[cfg(feature = "std")]
+pub mod linux;
+#[cfg(feature = "std")]
+pub mod mock;
+#[cfg(feature = "rp2040")]
+pub mod uart;
+#[cfg(feature = "udp")]
+pub mod udp;
+#[cfg(feature = "rp2040")]
+pub mod usb;
+
+#[cfg(feature = "rp2040")]
+pub use usb::{init_usb_transport, UsbTransport};
+/// Framing of the XRCE serial protocol, as the client frames byte streams. Not used by the
+/// transports, it documents and tests the format on the host, e.g. to decode captured traffic.
+pub use xrce_framing as framing;
+
+/// How long writes wait for the data to be queued and sent before reporting an error
+pub const WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+
+static RECEIVED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// Wakes `RclcExecutor::run`. Transports call this whenever data for the client arrived,
+/// transports that don't are polled instead.
+pub fn notify_received() {
+    RECEIVED.signal(());
 }
 
-pub fn usb_config() -> embassy_usb::Config<'static> {
-    let mut config = embassy_usb::Config::new(0xc0de, 0xcafe);
-    config.manufacturer = Some("Moonforge");
-    config.product = Some("Eir - robot doctor");
-    config.serial_number = Some("12345678");
-    config.max_power = 100;
-    config.max_packet_size_0 = 64;
-
-    // Required for windows compatibility.
-    // https://developer.nordicsemi.com/nRF_Connect_SDK/doc/1.9.1/kconfig/CONFIG_CDC_ACM_IAD.html#help
-    config.device_class = 0xEF;
-    config.device_sub_class = 0x02;
-    config.device_protocol = 0x01;
-    config.composite_with_iads = true;
-    config
+/// Forgets earlier notifications, the data they announced is about to be read
+pub(crate) fn reset_received() {
+    RECEIVED.reset();
 }
 
-fn usb_builder(
-    usb: embassy_rp::peripherals::USB,
-) -> embassy_usb::Builder<'static, embassy_rp::usb::Driver<'static, embassy_rp::peripherals::USB>> {
-    let config = usb_config();
-    let driver = Driver::new(usb, Irqs);
-    static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
-    static BOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
-    static CONTROL_BUF: StaticCell<[u8; 64]> = StaticCell::new();
-
-    let builder = embassy_usb::Builder::new(
-        driver,
-        config,
-        CONFIG_DESCRIPTOR.init([0; 256]),
-        BOS_DESCRIPTOR.init([0; 256]),
-        &mut [], // no msos descriptors
-        CONTROL_BUF.init([0; 64]),
-    );
-    builder
+/// Waits until data arrives or `timeout` passes
+pub(crate) async fn wait_received(timeout: Duration) {
+    let _ = with_timeout(timeout, RECEIVED.wait()).await;
 }
 
-pub fn init_usb(
-    peri: embassy_rp::peripherals::USB,
-) -> (cdc_acm::CdcAcmClass<'static, MyUsbDriver>, MyUsbDevice) {
-    let mut builder = usb_builder(peri);
-    let class = {
-        static STATE: StaticCell<State> = StaticCell::new();
-        let state = STATE.init(State::new());
-
-        CdcAcmClass::new(&mut builder, state, 64)
-    };
-
-    let usb = builder.build();
-
-    (class, usb)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "defmt", derive(defmt::Format))]
+pub enum TransportError {
+    /// The data could not be sent in time
+    Timeout,
+    /// The message does not fit into the transport's MTU
+    TooLarge,
+    /// The underlying link failed, e.g. the USB cable was unplugged
+    Link,
 }
 
-pub async fn init_usb_transport(peri: embassy_rp::peripherals::USB, spawner: &Spawner) {
-    // TODO: we could check that this runs in interrupt mode
-    let (mut class, usb) = init_usb(peri);
+/// Link to the agent, used by micro-ROS through the XRCE custom transport callbacks.
+/// All the methods are called from thread mode and may block.
+pub trait Transport {
+    /// Whether the XRCE client frames the messages itself, which byte streams require.
+    /// Without framing, every `write` sends one message and every `read` returns one message.
+    const FRAMING: bool;
 
-    defmt::unwrap!(spawner.spawn(usb_task(usb)));
-
-    defmt::error!("waiting for usb");
-    class.wait_connection().await;
-    defmt::error!("we have usb");
+    fn open(&mut self) -> bool {
+        true
+    }
 
-    let (sender, receiver) = class.split();
+    fn close(&mut self) -> bool {
+        true
+    }
 
-    defmt::unwrap!(spawner.spawn(sender_task(SENDER_CHANNEL.receiver(), sender)));
-    defmt::unwrap!(spawner.spawn(receiver_task(RECEIVER_CHANNEL.sender(), receiver)));
-}
+    /// Sends all of `data` and returns its length
+    fn write(&mut self, data: &[u8]) -> Result<usize, TransportError>;
 
-#[embassy_executor::task]
-pub async fn usb_task(mut usb: MyUsbDevice) -> ! {
-    usb.run().await
+    /// Waits up to `timeout` for data, returns the number of bytes read into `buffer`.
+    /// Running out of time is not an error, fewer bytes or none at all are returned instead.
+    fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> Result<usize, TransportError>;
 }
 
-pub const BUFFER_LEN: usize = 1024;
-pub const QUEUE_LEN: usize = 2;
-
-#[derive(defmt::Format)]
-pub struct Buffer {
-    inner: [u8; BUFFER_LEN],
-    used: usize,
+/// Makes micro-ROS use `transport`, which is passed to the callbacks in `uxrCustomTransport.args`.
+/// Has to be called before creating the support.
+pub fn init_rmw_transport<T: Transport>(transport: &'static mut T) {
+    // TODO: we could check that this runs in thread mode
+    unsafe {
+        rmw_uros_set_custom_transport(
+            T::FRAMING,
+            transport as *mut T as _,
+            Some(transport_open::<T>),
+            Some(transport_close::<T>),
+            Some(transport_write::<T>),
+            Some(transport_read::<T>),
+        )
+    };
 }
 
-impl Buffer {}
-
-pub static SENDER_CHANNEL: Channel<CriticalSectionRawMutex, Buffer, QUEUE_LEN> = Channel::new();
-pub static RECEIVER_CHANNEL: Channel<CriticalSectionRawMutex, u8, BUFFER_LEN> = Channel::new();
-
-#[embassy_executor::task]
-pub async fn sender_task(
-    receiver: channel::Receiver<'static, CriticalSectionRawMutex, Buffer, QUEUE_LEN>,
-    mut sender: cdc_acm::Sender<'static, MyUsbDriver>,
-) {
-    loop {
-        let buffer = receiver.receive().await;
-        defmt::unwrap!(sender.write_packet(&buffer.inner[..buffer.used]).await);
-    }
+unsafe fn from_args<'a, T: Transport>(transport: *mut uxrCustomTransport) -> &'a mut T {
+    &mut *((*transport).args as *mut T)
 }
 
-#[embassy_executor::task]
-pub async fn receiver_task(
-    sender: channel::Sender<'static, CriticalSectionRawMutex, u8, BUFFER_LEN>,
-    mut receiver: cdc_acm::Receiver<'static, MyUsbDriver>,
-) {
-    loop {
-        let mut buffer = [0u8; BUFFER_LEN];
-        let received = defmt::unwrap!(receiver.read_packet(&mut buffer[..]).await);
-        for &b in &buffer[..received] {
-            sender.send(b).await;
-        }
-    }
-}
-#[no_mangle]
-pub extern "C" fn transport_open(_transport: *mut uxrCustomTransport) -> bool {
-    true
+extern "C" fn transport_open<T: Transport>(transport: *mut uxrCustomTransport) -> bool {
+    unsafe { from_args::<T>(transport) }.open()
 }
 
-#[no_mangle]
-pub extern "C" fn transport_close(_transport: *mut uxrCustomTransport) -> bool {
-    true
+extern "C" fn transport_close<T: Transport>(transport: *mut uxrCustomTransport) -> bool {
+    unsafe { from_args::<T>(transport) }.close()
 }
 
-#[no_mangle]
-pub extern "C" fn transport_write(
-    _transport: *mut uxrCustomTransport,
+extern "C" fn transport_write<T: Transport>(
+    transport: *mut uxrCustomTransport,
     buf: *const u8,
     len: usize,
     err: *mut u8,
 ) -> usize {
-    defmt::trace!("write requested: {} bytes", len);
-    if len > BUFFER_LEN {
-        defmt::panic!("input buffer too large, split into multiple");
-    }
-    let mut buffer = Buffer {
-        inner: [0u8; BUFFER_LEN],
-        used: len,
-    };
-    unsafe {
-        buffer.inner[..len].copy_from_slice(core::slice::from_raw_parts(buf, len));
+    let transport = unsafe { from_args::<T>(transport) };
+    let data = unsafe { core::slice::from_raw_parts(buf, len) };
+
+    match transport.write(data) {
+        Ok(written) => written,
+        Err(e) => {
+            warn!("transport write failed: {:?}", e);
+            report_error(err);
+            0
+        }
     }
-    defmt::unwrap!(SENDER_CHANNEL.try_send(buffer));
-    // TODO: we must wait until the data is sent before leaving this function
-
-    len
 }
 
-#[no_mangle]
-pub extern "C" fn transport_read(
-    _transport: *mut uxrCustomTransport,
+extern "C" fn transport_read<T: Transport>(
+    transport: *mut uxrCustomTransport,
     buf: *mut u8,
     len: usize,
     timeout: i32,
     err: *mut u8,
 ) -> usize {
-    defmt::trace!("read requested: {}", len);
-    let timeout = Duration::from_millis(timeout as u64);
-    let start_time = Instant::now();
-
+    let transport = unsafe { from_args::<T>(transport) };
     let buffer = unsafe { core::slice::from_raw_parts_mut(buf, len) };
-    'outer: for i in 0..len {
-        while Instant::now() < (start_time + timeout) {
-            if let Ok(byte) = RECEIVER_CHANNEL.try_receive() {
-                buffer[i] = byte;
-                continue 'outer;
-            }
+    let timeout = Duration::from_millis(timeout.max(0) as u64);
+
+    match transport.read(buffer, timeout) {
+        Ok(read) => read,
+        Err(e) => {
+            warn!("transport read failed: {:?}", e);
+            report_error(err);
+            0
         }
-        defmt::trace!("timeout while reading");
+    }
+}
+
+fn report_error(err: *mut u8) {
+    if !err.is_null() {
         unsafe { *err = 1 };
-        return i;
+    }
+}
+
+/// Runs the future to completion in thread mode, sleeping with WFE until it is woken.
+/// Wakers fire from the interrupt executor or the time driver, both send an event to the core.
+/// Timeouts work inside, as embassy-time's generic timer queue accepts wakers of any executor.
+#[cfg(not(feature = "std"))]
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    use core::task::{RawWaker, RawWakerVTable};
+
+    const VTABLE: RawWakerVTable = RawWakerVTable::new(
+        |_| RawWaker::new(core::ptr::null(), &VTABLE),
+        |_| cortex_m::asm::sev(),
+        |_| cortex_m::asm::sev(),
+        |_| {},
+    );
+
+    let waker = unsafe { Waker::from_raw(RawWaker::new(core::ptr::null(), &VTABLE)) };
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        // returns right away if an event arrived since the poll
+        cortex_m::asm::wfe();
+    }
+}
+
+/// Runs the future to completion on the calling thread, parking it until it is woken.
+/// Like on the target, timeouts rely on the generic timer queue.
+#[cfg(feature = "std")]
+#[allow(dead_code)] // the host transport is blocking
+pub(crate) fn block_on<F: Future>(future: F) -> F::Output {
+    struct ThreadWaker(std::thread::Thread);
+
+    impl std::task::Wake for ThreadWaker {
+        fn wake(self: std::sync::Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    let waker = Waker::from(std::sync::Arc::new(ThreadWaker(std::thread::current())));
+    let mut context = Context::from_waker(&waker);
+    let mut future = pin!(future);
+    loop {
+        if let Poll::Ready(output) = future.as_mut().poll(&mut context) {
+            return output;
+        }
+        // returns right away if the waker fired since the poll
+        std::thread::park();
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests {
+    use super::*;
+    use embassy_time::{Instant, Timer};
+
+    #[test]
+    fn block_on_times_out() {
+        let start = Instant::now();
+        let result = block_on(with_timeout(
+            Duration::from_millis(20),
+            Timer::after_secs(10),
+        ));
+        assert!(result.is_err());
+        assert!(start.elapsed() >= Duration::from_millis(20));
     }
 
-    len
+    #[test]
+    fn block_on_wakes_before_timeout() {
+        static DATA: Signal<CriticalSectionRawMutex, u8> = Signal::new();
+        std::thread::spawn(|| {
+            std::thread::sleep(std::time::Duration::from_millis(10));
+            DATA.signal(42);
+        });
+
+        let result = block_on(with_timeout(Duration::from_secs(5), DATA.wait()));
+        assert_eq!(result, Ok(42));
+    }
+
+    #[test]
+    fn embassy_block_on_times_out() {
+        let result = embassy_futures::block_on(with_timeout(
+            Duration::from_millis(20),
+            Timer::after_secs(10),
+        ));
+        assert!(result.is_err());
+    }
 }
@@ -0,0 +1,80 @@
+//! XRCE transport over a host UDP socket, for running the micro-ROS code on Linux during
+//! development and in CI, e.g. against `micro-ros-agent udp4 --port 8888` on the same machine.
+
+use std::io::ErrorKind;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+
+use embassy_time::Duration;
+
+use super::{Transport, TransportError};
+
+/// Port the micro-ROS agent listens on by default
+pub const DEFAULT_AGENT_PORT: u16 = 8888;
+
+pub struct UdpTransport {
+    socket: UdpSocket,
+    agent: SocketAddr,
+}
+
+impl UdpTransport {
+    /// Binds an ephemeral port and talks to the agent at `agent`
+    pub fn new(agent: impl ToSocketAddrs) -> std::io::Result<Self> {
+        let agent = agent
+            .to_socket_addrs()?
+            .next()
+            .ok_or(ErrorKind::AddrNotAvailable)?;
+        let local: SocketAddr = if agent.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(local)?;
+        socket.connect(agent)?;
+        Ok(Self { socket, agent })
+    }
+
+    /// Agent on this machine listening on `DEFAULT_AGENT_PORT`
+    pub fn localhost() -> std::io::Result<Self> {
+        Self::new(("127.0.0.1", DEFAULT_AGENT_PORT))
+    }
+
+    pub fn agent(&self) -> SocketAddr {
+        self.agent
+    }
+}
+
+impl Transport for UdpTransport {
+    /// Datagrams keep the message boundaries
+    const FRAMING: bool = false;
+
+    fn write(&mut self, data: &[u8]) -> Result<usize, TransportError> {
+        trace!("udp write requested: {} bytes", data.len());
+        match self.socket.send(data) {
+            Ok(sent) if sent == data.len() => Ok(sent),
+            Ok(_) => Err(TransportError::TooLarge),
+            Err(e) => {
+                warn!("udp send failed: {:?}", e);
+                Err(TransportError::Link)
+            }
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> Result<usize, TransportError> {
+        // a zero timeout would block forever
+        let timeout = core::time::Duration::from_micros(timeout.as_micros().max(1));
+        if let Err(e) = self.socket.set_read_timeout(Some(timeout)) {
+            warn!("udp timeout not applied: {:?}", e);
+            return Err(TransportError::Link);
+        }
+
+        // the connected socket only receives datagrams from the agent
+        match self.socket.recv(buffer) {
+            Ok(received) => Ok(received),
+            Err(e) if matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut) => Ok(0),
+            Err(e) => {
+                warn!("udp receive failed: {:?}", e);
+                Err(TransportError::Link)
+            }
+        }
+    }
+}
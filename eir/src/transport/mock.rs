@@ -0,0 +1,913 @@
+//! In-memory transport and a scripted fake XRCE-DDS agent, for testing the micro-ROS code on the
+//! host without a board or a real agent.
+//!
+//! The fake agent answers inside `write`, so its replies are queued before the client reads and
+//! the tests stay deterministic. It understands just enough of the XRCE protocol for micro-ROS:
+//! pings, session creation, entity creation and deletion, writes, reads, service replies and
+//! heartbeats.
+//! Fragmented messages are not supported, every message has to fit into the client's MTU.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+
+use embassy_time::Duration;
+
+use super::{notify_received, Transport, TransportError};
+
+/// XRCE submessage ids
+mod submessage {
+    pub const CREATE_CLIENT: u8 = 0;
+    pub const CREATE: u8 = 1;
+    pub const GET_INFO: u8 = 2;
+    pub const DELETE: u8 = 3;
+    pub const STATUS_AGENT: u8 = 4;
+    pub const STATUS: u8 = 5;
+    pub const INFO: u8 = 6;
+    pub const WRITE_DATA: u8 = 7;
+    pub const READ_DATA: u8 = 8;
+    pub const DATA: u8 = 9;
+    pub const ACKNACK: u8 = 10;
+    pub const HEARTBEAT: u8 = 11;
+}
+
+/// Flag of the submessages serialized in little endian, which is all the agent sends
+const LITTLE_ENDIAN: u8 = 0x01;
+/// Session ids from this one on are not followed by the client key
+const SESSION_ID_WITHOUT_CLIENT_KEY: u8 = 0x80;
+/// Stream ids from this one on are reliable
+const FIRST_RELIABLE_STREAM: u8 = 0x80;
+const OBJK_AGENT: u8 = 0x0D;
+const STATUS_OK: u8 = 0x00;
+
+/// Transport passing whole messages through in-memory queues.
+/// Without an agent, the test plays the agent through the `MockHandle`.
+pub struct MockTransport {
+    shared: Arc<Shared>,
+}
+
+/// Access to the queues and the agent of a `MockTransport` that micro-ROS already owns
+#[derive(Clone)]
+pub struct MockHandle {
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    incoming: Condvar,
+}
+
+struct State {
+    /// Messages waiting to be read by the client
+    incoming: VecDeque<Vec<u8>>,
+    /// Every message written by the client
+    sent: Vec<Vec<u8>>,
+    agent: Option<FakeAgent>,
+    link_up: bool,
+}
+
+impl State {
+    /// Moves the replies of the agent to the client's queue
+    fn collect_replies(&mut self) {
+        if let Some(agent) = self.agent.as_mut() {
+            self.incoming.extend(agent.outbox.drain(..));
+        }
+    }
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::with(None)
+    }
+
+    /// Transport answered by `agent`
+    pub fn with_agent(agent: FakeAgent) -> Self {
+        Self::with(Some(agent))
+    }
+
+    fn with(agent: Option<FakeAgent>) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                state: Mutex::new(State {
+                    incoming: VecDeque::new(),
+                    sent: Vec::new(),
+                    agent,
+                    link_up: true,
+                }),
+                incoming: Condvar::new(),
+            }),
+        }
+    }
+
+    pub fn handle(&self) -> MockHandle {
+        MockHandle {
+            shared: self.shared.clone(),
+        }
+    }
+}
+
+impl Default for MockTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for MockTransport {
+    /// The queues keep the message boundaries
+    const FRAMING: bool = false;
+
+    fn write(&mut self, data: &[u8]) -> Result<usize, TransportError> {
+        let mut state = self.shared.lock();
+        if !state.link_up {
+            return Err(TransportError::Link);
+        }
+
+        state.sent.push(data.to_vec());
+        if let Some(agent) = state.agent.as_mut() {
+            agent.handle(data);
+            state.collect_replies();
+            self.shared.notify();
+        }
+        Ok(data.len())
+    }
+
+    fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> Result<usize, TransportError> {
+        let timeout = std::time::Duration::from_micros(timeout.as_micros());
+        let state = self.shared.lock();
+        let (mut state, _) = self
+            .shared
+            .incoming
+            .wait_timeout_while(state, timeout, |state| state.incoming.is_empty())
+            .unwrap_or_else(|e| e.into_inner());
+
+        let message = state.incoming.pop_front();
+        if !state.incoming.is_empty() {
+            notify_received();
+        }
+        match message {
+            Some(message) if message.len() > buffer.len() => Err(TransportError::TooLarge),
+            Some(message) => {
+                buffer[..message.len()].copy_from_slice(&message);
+                Ok(message.len())
+            }
+            None => Ok(0),
+        }
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // a panicking test must not take the others down with it
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    fn notify(&self) {
+        self.incoming.notify_all();
+        notify_received();
+    }
+}
+
+impl MockHandle {
+    /// Queues `message` for the client
+    pub fn push(&self, message: &[u8]) {
+        self.shared.lock().incoming.push_back(message.to_vec());
+        self.shared.notify();
+    }
+
+    /// Every message the client wrote so far
+    pub fn sent(&self) -> Vec<Vec<u8>> {
+        self.shared.lock().sent.clone()
+    }
+
+    pub fn clear_sent(&self) {
+        self.shared.lock().sent.clear();
+    }
+
+    /// While the link is down, writes fail with `TransportError::Link`
+    pub fn set_link_up(&self, up: bool) {
+        self.shared.lock().link_up = up;
+    }
+
+    /// Runs `f` with the agent answering the transport, e.g. to inspect the created entities or
+    /// to deliver data. Returns `None` if the transport has no agent.
+    pub fn agent<R>(&self, f: impl FnOnce(&mut FakeAgent) -> R) -> Option<R> {
+        let mut state = self.shared.lock();
+        let result = state.agent.as_mut().map(f);
+        state.collect_replies();
+        self.shared.notify();
+        result
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ObjectKind {
+    Participant,
+    Topic,
+    Publisher,
+    Subscriber,
+    DataWriter,
+    DataReader,
+    Requester,
+    Replier,
+    Other(u8),
+}
+
+impl From<u8> for ObjectKind {
+    fn from(kind: u8) -> Self {
+        match kind {
+            0x01 => ObjectKind::Participant,
+            0x02 => ObjectKind::Topic,
+            0x03 => ObjectKind::Publisher,
+            0x04 => ObjectKind::Subscriber,
+            0x05 => ObjectKind::DataWriter,
+            0x06 => ObjectKind::DataReader,
+            0x07 => ObjectKind::Requester,
+            0x08 => ObjectKind::Replier,
+            other => ObjectKind::Other(other),
+        }
+    }
+}
+
+/// Entity created by the client
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entity {
+    /// Raw XRCE object id, the lowest nibble holds the kind
+    pub object_id: [u8; 2],
+    pub kind: ObjectKind,
+    /// Name of the entity, for data writers and readers the name of their topic.
+    /// ROS topics are prefixed with `rt/`, e.g. `rt/pico_publisher`.
+    pub name: Option<String>,
+    /// Stream the creation arrived on
+    pub stream_id: u8,
+}
+
+/// Data the client wrote
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Written {
+    /// Request id of the write, for service requests the sequence number of the call
+    pub request_id: [u8; 2],
+    pub object_id: [u8; 2],
+    /// Topic of the data writer, if it is known
+    pub topic: Option<String>,
+    pub stream_id: u8,
+    /// The serialized message, without encapsulation
+    pub data: Vec<u8>,
+}
+
+impl Written {
+    pub fn is_reliable(&self) -> bool {
+        self.stream_id >= FIRST_RELIABLE_STREAM
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ReadRequest {
+    request: [u8; 4],
+    stream_id: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    session_id: u8,
+    stream_id: u8,
+    sequence: u16,
+    key: Option<[u8; 4]>,
+}
+
+impl Header {
+    fn parse(message: &[u8]) -> Option<(Header, usize)> {
+        let &[session_id, stream_id, seq_low, seq_high, ..] = message else {
+            return None;
+        };
+        let mut header = Header {
+            session_id,
+            stream_id,
+            sequence: u16::from_le_bytes([seq_low, seq_high]),
+            key: None,
+        };
+        if session_id < SESSION_ID_WITHOUT_CLIENT_KEY {
+            header.key = Some(message.get(4..8)?.try_into().ok()?);
+            return Some((header, 8));
+        }
+        Some((header, 4))
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&[self.session_id, self.stream_id]);
+        out.extend_from_slice(&self.sequence.to_le_bytes());
+        if let Some(key) = self.key {
+            out.extend_from_slice(&key);
+        }
+    }
+}
+
+/// Scripted XRCE-DDS agent, answering every request with success while it is available
+pub struct FakeAgent {
+    available: bool,
+    pings: usize,
+    sessions: usize,
+    session: Option<Header>,
+    entities: Vec<Entity>,
+    written: Vec<Written>,
+    reads: Vec<ReadRequest>,
+    /// Last sequence number received on each reliable stream of the client, to drop resends
+    received: [Option<u16>; 128],
+    /// Next sequence number of each stream towards the client
+    sequences: [u16; 256],
+    outbox: Vec<Vec<u8>>,
+}
+
+impl Default for FakeAgent {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FakeAgent {
+    pub fn new() -> Self {
+        Self {
+            available: true,
+            pings: 0,
+            sessions: 0,
+            session: None,
+            entities: Vec::new(),
+            written: Vec::new(),
+            reads: Vec::new(),
+            received: [None; 128],
+            sequences: [0; 256],
+            outbox: Vec::new(),
+        }
+    }
+
+    /// An unavailable agent ignores everything, as if the host went away
+    pub fn set_available(&mut self, available: bool) {
+        self.available = available;
+    }
+
+    pub fn is_available(&self) -> bool {
+        self.available
+    }
+
+    /// Number of pings answered
+    pub fn pings(&self) -> usize {
+        self.pings
+    }
+
+    /// Number of sessions created
+    pub fn sessions(&self) -> usize {
+        self.sessions
+    }
+
+    /// Entities of the current session, in creation order
+    pub fn entities(&self) -> &[Entity] {
+        &self.entities
+    }
+
+    pub fn entity(&self, kind: ObjectKind, name: &str) -> Option<&Entity> {
+        self.entities
+            .iter()
+            .find(|entity| entity.kind == kind && entity.name.as_deref() == Some(name))
+    }
+
+    /// Everything the client wrote, in order
+    pub fn written(&self) -> &[Written] {
+        &self.written
+    }
+
+    pub fn written_to<'a>(&'a self, topic: &'a str) -> impl Iterator<Item = &'a Written> + 'a {
+        self.written
+            .iter()
+            .filter(move |written| written.topic.as_deref() == Some(topic))
+    }
+
+    /// Requests the service clients sent through their requesters, in order
+    pub fn requests(&self) -> impl Iterator<Item = &Written> + '_ {
+        self.written.iter().filter(move |written| {
+            self.entities.iter().any(|entity| {
+                entity.object_id == written.object_id && entity.kind == ObjectKind::Requester
+            })
+        })
+    }
+
+    /// Sends `data`, a serialized response, to the service client that sent `request`.
+    /// Returns false if its requester did not request data.
+    pub fn reply(&mut self, request: &Written, data: &[u8]) -> bool {
+        let Some(session) = self.session else {
+            return false;
+        };
+        let Some(read) = self
+            .reads
+            .iter()
+            .find(|read| read.request[2..] == request.object_id)
+            .copied()
+        else {
+            return false;
+        };
+
+        // the reply refers to the request it answers, which the client matches by its id
+        let mut payload = read.request.to_vec();
+        payload.extend_from_slice(&request.request_id);
+        payload.extend_from_slice(&request.object_id);
+        payload.extend_from_slice(data);
+        self.send(session, read.stream_id, submessage::DATA, &payload);
+        true
+    }
+
+    /// Whether the data reader of `topic` asked for its data on a reliable stream,
+    /// `None` if it did not request data yet
+    pub fn reads_reliable(&self, topic: &str) -> Option<bool> {
+        self.reads_of(topic)
+            .next()
+            .map(|read| read.stream_id >= FIRST_RELIABLE_STREAM)
+    }
+
+    /// Pending data requests of the data readers of `topic`
+    fn reads_of<'a>(&'a self, topic: &'a str) -> impl Iterator<Item = &'a ReadRequest> + 'a {
+        self.reads.iter().filter(move |read| {
+            self.entities.iter().any(|entity| {
+                entity.object_id == [read.request[2], read.request[3]]
+                    && entity.name.as_deref() == Some(topic)
+            })
+        })
+    }
+
+    /// Sends `data`, a serialized message, to every data reader of `topic` that requested data.
+    /// Returns false if there is none.
+    pub fn deliver(&mut self, topic: &str, data: &[u8]) -> bool {
+        let Some(session) = self.session else {
+            return false;
+        };
+
+        let readers: Vec<ReadRequest> = self.reads_of(topic).copied().collect();
+
+        for read in &readers {
+            let mut payload = read.request.to_vec();
+            payload.extend_from_slice(data);
+            self.send(session, read.stream_id, submessage::DATA, &payload);
+        }
+        !readers.is_empty()
+    }
+
+    /// Handles one message of the client, queuing the replies in the outbox
+    fn handle(&mut self, message: &[u8]) {
+        if !self.available {
+            return;
+        }
+        let Some((header, mut offset)) = Header::parse(message) else {
+            return;
+        };
+
+        if header.stream_id >= FIRST_RELIABLE_STREAM {
+            let last = &mut self.received[(header.stream_id - FIRST_RELIABLE_STREAM) as usize];
+            let resent = last.is_some_and(|last| header.sequence.wrapping_sub(last) as i16 <= 0);
+            if !resent {
+                *last = Some(header.sequence);
+            }
+            // acknowledge right away, so the client frees its history
+            self.acknowledge(header, header.stream_id);
+            if resent {
+                return;
+            }
+        }
+
+        while let Some(start) = align(offset).checked_add(4) {
+            let Some(&[id, _flags, low, high]) = message.get(start - 4..start) else {
+                break;
+            };
+            let end = start + u16::from_le_bytes([low, high]) as usize;
+            let Some(payload) = message.get(start..end) else {
+                break;
+            };
+            self.handle_submessage(header, id, payload);
+            offset = end;
+        }
+    }
+
+    fn handle_submessage(&mut self, header: Header, id: u8, payload: &[u8]) {
+        let Some(request) = payload.get(..4) else {
+            return;
+        };
+        let request: [u8; 4] = request.try_into().unwrap();
+
+        match id {
+            submessage::CREATE_CLIENT => self.create_session(header, payload),
+            submessage::GET_INFO => {
+                self.pings += 1;
+                let mut info = request.to_vec();
+                info.extend_from_slice(&[STATUS_OK, 0]);
+                // no configuration, agent activity with availability 1 and no locators
+                info.extend_from_slice(&[0, 1, OBJK_AGENT, 0]);
+                info.extend_from_slice(&1i16.to_le_bytes());
+                info.extend_from_slice(&[0; 4]);
+                self.send(header, header.stream_id, submessage::INFO, &info);
+            }
+            submessage::CREATE => {
+                let object_id = [request[2], request[3]];
+                let entity = Entity {
+                    object_id,
+                    kind: ObjectKind::from(object_id[1] & 0x0F),
+                    name: self.entity_name(&payload[4..]),
+                    stream_id: header.stream_id,
+                };
+                // creating an existing entity replaces it
+                self.entities.retain(|e| e.object_id != object_id);
+                self.entities.push(entity);
+                self.status(header, request);
+            }
+            submessage::DELETE => {
+                self.entities
+                    .retain(|e| e.object_id != [request[2], request[3]]);
+                self.reads.retain(|read| read.request[2..] != request[2..]);
+                self.status(header, request);
+            }
+            submessage::WRITE_DATA => {
+                let object_id = [request[2], request[3]];
+                let topic = self
+                    .entities
+                    .iter()
+                    .find(|entity| entity.object_id == object_id)
+                    .and_then(|entity| entity.name.clone());
+                self.written.push(Written {
+                    request_id: [request[0], request[1]],
+                    object_id,
+                    topic,
+                    stream_id: header.stream_id,
+                    data: payload[4..].to_vec(),
+                });
+            }
+            submessage::READ_DATA => {
+                let stream_id = match payload.get(4) {
+                    Some(&stream_id) if stream_id != 0 => stream_id,
+                    _ => header.stream_id,
+                };
+                self.reads.retain(|read| read.request[2..] != request[2..]);
+                self.reads.push(ReadRequest { request, stream_id });
+            }
+            submessage::HEARTBEAT => {
+                // first and last unacknowledged sequence numbers, then the stream
+                if let Some(&stream_id) = payload.get(4) {
+                    self.acknowledge(header, stream_id);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn create_session(&mut self, header: Header, payload: &[u8]) {
+        // the client representation holds the id and key of the new session
+        let (Some(key), Some(&session_id)) = (payload.get(12..16), payload.get(16)) else {
+            return;
+        };
+
+        self.sessions += 1;
+        self.entities.clear();
+        self.reads.clear();
+        self.received = [None; 128];
+        self.sequences = [0; 256];
+
+        let session = Header {
+            session_id,
+            stream_id: 0,
+            sequence: 0,
+            key: (session_id < SESSION_ID_WITHOUT_CLIENT_KEY).then(|| key.try_into().unwrap()),
+        };
+        self.session = Some(session);
+
+        // status, then the agent representation: cookie, version 1.0, vendor, no properties
+        let mut status = vec![STATUS_OK, 0];
+        status.extend_from_slice(b"XRCE");
+        status.extend_from_slice(&[1, 0, 0x0F, 0x0F, 0]);
+        self.send(session, header.stream_id, submessage::STATUS_AGENT, &status);
+    }
+
+    fn status(&mut self, header: Header, request: [u8; 4]) {
+        let mut status = request.to_vec();
+        status.extend_from_slice(&[STATUS_OK, 0]);
+        self.send(header, header.stream_id, submessage::STATUS, &status);
+    }
+
+    fn acknowledge(&mut self, header: Header, stream_id: u8) {
+        let Some(index) = stream_id.checked_sub(FIRST_RELIABLE_STREAM) else {
+            return;
+        };
+        let first_unacked = self.received[index as usize].map_or(0, |last| last.wrapping_add(1));
+        let mut acknack = first_unacked.to_le_bytes().to_vec();
+        acknack.extend_from_slice(&[0, 0, stream_id]);
+        self.send(header, 0, submessage::ACKNACK, &acknack);
+    }
+
+    /// Queues a message with a single submessage for the client
+    fn send(&mut self, header: Header, stream_id: u8, id: u8, payload: &[u8]) {
+        let sequence = if stream_id == 0 {
+            0
+        } else {
+            let next = &mut self.sequences[stream_id as usize];
+            let sequence = *next;
+            *next = next.wrapping_add(1);
+            sequence
+        };
+
+        let mut message = Vec::with_capacity(12 + payload.len());
+        Header {
+            stream_id,
+            sequence,
+            ..header
+        }
+        .write(&mut message);
+        message.extend_from_slice(&[id, LITTLE_ENDIAN]);
+        message.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        message.extend_from_slice(payload);
+        self.outbox.push(message);
+    }
+
+    /// Name of a created entity from its object variant, which starts with the kind and the
+    /// representation format. Data writers and readers created in binary refer to their topic.
+    fn entity_name(&self, variant: &[u8]) -> Option<String> {
+        const REFERENCE: u8 = 0x01;
+        const XML: u8 = 0x02;
+        const BINARY: u8 = 0x03;
+
+        let kind = ObjectKind::from(*variant.first()?);
+        match *variant.get(1)? {
+            REFERENCE => cdr_string(variant, 4),
+            XML => {
+                let xml = cdr_string(variant, 4)?;
+                let start = xml.find("<name>")? + "<name>".len();
+                let end = start + xml[start..].find("</name>")?;
+                Some(xml[start..end].to_string())
+            }
+            // the sequence length comes first, then the binary object starting with a name
+            BINARY => cdr_string(variant, 8).or_else(|| {
+                if !matches!(kind, ObjectKind::DataWriter | ObjectKind::DataReader) {
+                    return None;
+                }
+                let topic_id = variant.get(8..10)?;
+                self.entities
+                    .iter()
+                    .find(|entity| entity.kind == ObjectKind::Topic && entity.object_id == topic_id)
+                    .and_then(|entity| entity.name.clone())
+            }),
+            _ => None,
+        }
+    }
+}
+
+fn align(offset: usize) -> usize {
+    (offset + 3) & !3
+}
+
+/// Reads a nul terminated CDR string whose length is at `offset`
+fn cdr_string(data: &[u8], offset: usize) -> Option<String> {
+    let length = u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?) as usize;
+    let bytes = data.get(offset + 4..(offset + 4).checked_add(length)?)?;
+    let (&0, text) = bytes.split_last()? else {
+        return None;
+    };
+    let text = core::str::from_utf8(text).ok()?;
+    text.chars()
+        .all(|c| c.is_ascii_graphic())
+        .then(|| text.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SESSION: u8 = 0x81;
+    const RELIABLE: u8 = 0x80;
+
+    fn message(stream_id: u8, sequence: u16, submessages: &[(u8, &[u8])]) -> Vec<u8> {
+        let mut message = vec![SESSION, stream_id];
+        message.extend_from_slice(&sequence.to_le_bytes());
+        for (id, payload) in submessages {
+            message.resize(align(message.len()), 0);
+            message.extend_from_slice(&[*id, LITTLE_ENDIAN]);
+            message.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+            message.extend_from_slice(payload);
+        }
+        message
+    }
+
+    fn create_session(transport: &mut MockTransport) {
+        let mut client = vec![0, 1, 0xFF, 0xFE];
+        client.extend_from_slice(b"XRCE");
+        client.extend_from_slice(&[1, 0, 0x0F, 0x0F, 0xAA, 0xBB, 0xCC, 0xDD, SESSION, 0]);
+        client.extend_from_slice(&512u16.to_le_bytes());
+        let create_client = message(0, 0, &[(submessage::CREATE_CLIENT, &client)]);
+        transport.write(&create_client).unwrap();
+    }
+
+    fn binary_entity(request: [u8; 4], kind: u8, name: &str) -> Vec<u8> {
+        let mut create = request.to_vec();
+        create.extend_from_slice(&[kind, 0x03, 0, 0]);
+        let mut object = ((name.len() + 1) as u32).to_le_bytes().to_vec();
+        object.extend_from_slice(name.as_bytes());
+        object.push(0);
+        create.extend_from_slice(&(object.len() as u32).to_le_bytes());
+        create.extend_from_slice(&object);
+        create
+    }
+
+    fn read(transport: &mut MockTransport) -> Vec<u8> {
+        let mut buffer = [0u8; 512];
+        let read = transport
+            .read(&mut buffer, Duration::from_millis(10))
+            .unwrap();
+        buffer[..read].to_vec()
+    }
+
+    /// Reads until a message carrying submessage `id` arrives
+    fn read_submessage(transport: &mut MockTransport, id: u8) -> Vec<u8> {
+        loop {
+            let reply = read(transport);
+            assert!(!reply.is_empty(), "no submessage {id} received");
+            if reply[4] == id {
+                return reply;
+            }
+        }
+    }
+
+    #[test]
+    fn answers_ping() {
+        let mut transport = MockTransport::with_agent(FakeAgent::new());
+        let ping = message(
+            0,
+            0,
+            &[(submessage::GET_INFO, &[0, 9, 0xFF, 0xFD, 3, 0, 0, 0])],
+        );
+        transport.write(&ping).unwrap();
+
+        let info = read(&mut transport);
+        assert_eq!(&info[..4], &[SESSION, 0, 0, 0]);
+        assert_eq!(&info[4..6], &[submessage::INFO, LITTLE_ENDIAN]);
+        // related request, status ok, activity of an available agent
+        assert_eq!(&info[8..14], &[0, 9, 0xFF, 0xFD, STATUS_OK, 0]);
+        assert_eq!(&info[15..17], &[1, OBJK_AGENT]);
+        assert_eq!(i16::from_le_bytes([info[18], info[19]]), 1);
+        assert_eq!(transport.handle().agent(|agent| agent.pings()), Some(1));
+    }
+
+    #[test]
+    fn unavailable_agent_stays_silent() {
+        let mut agent = FakeAgent::new();
+        agent.set_available(false);
+        let mut transport = MockTransport::with_agent(agent);
+        let ping = message(0, 0, &[(submessage::GET_INFO, &[0, 9, 0xFF, 0xFD])]);
+        transport.write(&ping).unwrap();
+
+        assert!(read(&mut transport).is_empty());
+        assert_eq!(transport.handle().sent(), vec![ping]);
+    }
+
+    #[test]
+    fn creates_session_and_entities() {
+        let mut transport = MockTransport::with_agent(FakeAgent::new());
+        create_session(&mut transport);
+        let status = read(&mut transport);
+        assert_eq!(&status[..4], &[SESSION, 0, 0, 0]);
+        assert_eq!(&status[4..6], &[submessage::STATUS_AGENT, LITTLE_ENDIAN]);
+        assert_eq!(status[8], STATUS_OK);
+
+        let topic = binary_entity([0, 2, 0x00, 0x12], 0x02, "rt/chatter");
+        let create = message(RELIABLE, 0, &[(submessage::CREATE, &topic)]);
+        transport.write(&create).unwrap();
+        let status = read_submessage(&mut transport, submessage::STATUS);
+        assert_eq!(status[1], RELIABLE);
+        assert_eq!(&status[8..14], &[0, 2, 0x00, 0x12, STATUS_OK, 0]);
+
+        // a resend of the same sequence number is acknowledged but not handled again
+        transport.write(&create).unwrap();
+        let entities = transport.handle().agent(|agent| agent.entities().to_vec());
+        assert_eq!(
+            entities,
+            Some(vec![Entity {
+                object_id: [0x00, 0x12],
+                kind: ObjectKind::Topic,
+                name: Some("rt/chatter".into()),
+                stream_id: RELIABLE,
+            }])
+        );
+    }
+
+    #[test]
+    fn records_written_data() {
+        let mut transport = MockTransport::with_agent(FakeAgent::new());
+        create_session(&mut transport);
+        let writer = binary_entity([0, 3, 0x00, 0x15], 0x05, "rt/chatter");
+        transport
+            .write(&message(RELIABLE, 0, &[(submessage::CREATE, &writer)]))
+            .unwrap();
+        transport
+            .write(&message(
+                0x01,
+                0,
+                &[(submessage::WRITE_DATA, &[0, 4, 0x00, 0x15, 42, 0, 0, 0])],
+            ))
+            .unwrap();
+
+        let written = transport
+            .handle()
+            .agent(|agent| agent.written_to("rt/chatter").cloned().collect::<Vec<_>>())
+            .unwrap();
+        assert_eq!(written.len(), 1);
+        assert_eq!(written[0].data, [42, 0, 0, 0]);
+        assert!(!written[0].is_reliable());
+    }
+
+    #[test]
+    fn delivers_to_readers() {
+        let mut transport = MockTransport::with_agent(FakeAgent::new());
+        let handle = transport.handle();
+        create_session(&mut transport);
+        let reader = binary_entity([0, 3, 0x00, 0x26], 0x06, "rt/chatter");
+        transport
+            .write(&message(RELIABLE, 0, &[(submessage::CREATE, &reader)]))
+            .unwrap();
+        transport
+            .write(&message(
+                RELIABLE,
+                1,
+                &[(
+                    submessage::READ_DATA,
+                    &[0, 5, 0x00, 0x26, RELIABLE, 0, 0, 0],
+                )],
+            ))
+            .unwrap();
+        assert_eq!(
+            handle.agent(|agent| agent.reads_reliable("rt/chatter")),
+            Some(Some(true))
+        );
+        assert_eq!(
+            handle.agent(|agent| agent.deliver("rt/other", &[1])),
+            Some(false)
+        );
+        assert_eq!(
+            handle.agent(|agent| agent.deliver("rt/chatter", &[7, 0, 0, 0])),
+            Some(true)
+        );
+
+        let data = read_submessage(&mut transport, submessage::DATA);
+        // the status of the creation came first on the reliable stream
+        assert_eq!(&data[..4], &[SESSION, RELIABLE, 1, 0]);
+        assert_eq!(&data[8..], &[0, 5, 0x00, 0x26, 7, 0, 0, 0]);
+    }
+
+    #[test]
+    fn replies_to_requests() {
+        let mut transport = MockTransport::with_agent(FakeAgent::new());
+        let handle = transport.handle();
+        create_session(&mut transport);
+        let requester = binary_entity([0, 3, 0x00, 0x17], 0x07, "mock_service");
+        transport
+            .write(&message(RELIABLE, 0, &[(submessage::CREATE, &requester)]))
+            .unwrap();
+        transport
+            .write(&message(
+                RELIABLE,
+                1,
+                &[(
+                    submessage::READ_DATA,
+                    &[0, 4, 0x00, 0x17, RELIABLE, 0, 0, 0],
+                )],
+            ))
+            .unwrap();
+        transport
+            .write(&message(
+                RELIABLE,
+                2,
+                &[
+                    (submessage::WRITE_DATA, &[0, 5, 0x00, 0x17, 1]),
+                    (submessage::WRITE_DATA, &[0, 6, 0x00, 0x17, 0]),
+                ],
+            ))
+            .unwrap();
+
+        let requests = handle
+            .agent(|agent| agent.requests().cloned().collect::<Vec<_>>())
+            .unwrap();
+        assert_eq!(requests.len(), 2);
+        assert_eq!(requests[0].request_id, [0, 5]);
+        assert_eq!(requests[1].data, [0]);
+        assert_eq!(
+            handle.agent(|agent| agent.reply(&requests[1], &[7])),
+            Some(true)
+        );
+
+        let data = read_submessage(&mut transport, submessage::DATA);
+        // the data request, then the answered request
+        assert_eq!(&data[8..], &[0, 4, 0x00, 0x17, 0, 6, 0x00, 0x17, 7]);
+    }
+
+    #[test]
+    fn link_down_fails_writes() {
+        let mut transport = MockTransport::new();
+        let handle = transport.handle();
+        handle.set_link_up(false);
+        assert_eq!(transport.write(&[1, 2, 3]), Err(TransportError::Link));
+
+        handle.set_link_up(true);
+        handle.push(&[4, 5]);
+        assert_eq!(transport.write(&[1, 2, 3]), Ok(3));
+        assert_eq!(read(&mut transport), [4, 5]);
+        assert!(read(&mut transport).is_empty());
+    }
+}
@@ -0,0 +1,61 @@
+//! XRCE transport over a UART, e.g. a direct serial or RS-485 link to the agent's serial mode.
+//!
+//! The UART is a byte stream like USB CDC, so the client frames the messages itself
+//! (`framing = true`) and reads return whatever bytes arrived. The frames on the wire are the ones
+//! `framing` encodes and decodes, see the `xrce-framing` crate for the format and its tests.
+//! Register the transport with `transport::init_rmw_transport`.
+
+use embassy_rp::uart::{self, BufferedUart, Instance};
+use embassy_time::{with_timeout, Duration};
+use embedded_io_async::{BufRead, Write};
+
+use super::{block_on, Transport, TransportError, WRITE_TIMEOUT};
+
+pub struct UartTransport<T: Instance + 'static> {
+    uart: BufferedUart<'static, T>,
+}
+
+impl<T: Instance + 'static> UartTransport<T> {
+    pub fn new(uart: BufferedUart<'static, T>) -> Self {
+        Self { uart }
+    }
+
+    /// Waits for received bytes and copies as many as fit into `buffer`
+    async fn receive(&mut self, buffer: &mut [u8]) -> Result<usize, uart::Error> {
+        let available = self.uart.fill_buf().await?;
+        let len = available.len().min(buffer.len());
+        buffer[..len].copy_from_slice(&available[..len]);
+        // the rest stays buffered for the next read
+        self.uart.consume(len);
+
+        Ok(len)
+    }
+}
+
+impl<T: Instance + 'static> Transport for UartTransport<T> {
+    /// The UART is a byte stream, the client has to frame the messages
+    const FRAMING: bool = true;
+
+    fn write(&mut self, data: &[u8]) -> Result<usize, TransportError> {
+        defmt::trace!("uart write requested: {} bytes", data.len());
+        match block_on(with_timeout(WRITE_TIMEOUT, self.uart.write_all(data))) {
+            Ok(Ok(())) => Ok(data.len()),
+            Ok(Err(e)) => {
+                defmt::warn!("uart write failed: {}", e);
+                Err(TransportError::Link)
+            }
+            Err(_) => Err(TransportError::Timeout),
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> Result<usize, TransportError> {
+        match block_on(with_timeout(timeout, self.receive(buffer))) {
+            Ok(Ok(received)) => Ok(received),
+            Ok(Err(e)) => {
+                defmt::warn!("uart read failed: {}", e);
+                Err(TransportError::Link)
+            }
+            Err(_) => Ok(0),
+        }
+    }
+}
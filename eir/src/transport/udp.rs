@@ -0,0 +1,85 @@
+//! XRCE transport over UDP/IPv4 using embassy-net, e.g. on a Pico W or with a W5500.
+//!
+//! Every datagram carries one message, so the client runs in the non-framed mode the agent
+//! expects on UDP, e.g. `micro-ros-agent udp4 --port 8888`. The network stack has to run in an
+//! interrupt executor with a higher priority than the micro-ROS code.
+//!
+//! The transport builds for the host with the `std` and `udp` features. Running it over
+//! embassy-net's TUN/TAP driver against a local agent is split out of this transport: `Stack` is
+//! not `Sync`, so on the host the stack cannot run in another thread while `read` and `write`
+//! block the calling one, and the host has no interrupt executor to run it in.
+
+use embassy_net::{
+    udp::{RecvError, UdpSocket},
+    IpAddress, IpEndpoint,
+};
+use embassy_time::{with_timeout, Duration};
+
+use super::{block_on, Transport, TransportError, WRITE_TIMEOUT};
+
+/// Port the micro-ROS agent listens on by default
+pub const DEFAULT_AGENT_PORT: u16 = 8888;
+
+pub struct UdpTransport<'a> {
+    socket: UdpSocket<'a>,
+    agent: IpEndpoint,
+}
+
+impl<'a> UdpTransport<'a> {
+    /// Talks to the agent at `agent` through `socket`, which has to be bound already,
+    /// e.g. with `socket.bind(0)` for an ephemeral port
+    pub fn new(socket: UdpSocket<'a>, agent: IpEndpoint) -> Self {
+        Self { socket, agent }
+    }
+
+    /// Agent at `address` listening on `DEFAULT_AGENT_PORT`
+    pub fn with_agent_address(socket: UdpSocket<'a>, address: IpAddress) -> Self {
+        Self::new(socket, IpEndpoint::new(address, DEFAULT_AGENT_PORT))
+    }
+
+    pub fn agent(&self) -> IpEndpoint {
+        self.agent
+    }
+
+    pub fn set_agent(&mut self, agent: IpEndpoint) {
+        self.agent = agent;
+    }
+
+    /// Waits for the next datagram from the agent, datagrams from anyone else are dropped
+    async fn receive(&self, buffer: &mut [u8]) -> Result<usize, TransportError> {
+        loop {
+            match self.socket.recv_from(buffer).await {
+                Ok((received, sender)) if sender == self.agent => return Ok(received),
+                Ok((_, sender)) => debug!("dropping datagram from {}", sender),
+                Err(RecvError::Truncated) => {
+                    warn!("received a datagram larger than {} bytes", buffer.len());
+                    return Err(TransportError::TooLarge);
+                }
+            }
+        }
+    }
+}
+
+impl Transport for UdpTransport<'_> {
+    /// Datagrams keep the message boundaries
+    const FRAMING: bool = false;
+
+    fn write(&mut self, data: &[u8]) -> Result<usize, TransportError> {
+        trace!("udp write requested: {} bytes", data.len());
+        match block_on(with_timeout(
+            WRITE_TIMEOUT,
+            self.socket.send_to(data, self.agent),
+        )) {
+            Ok(Ok(())) => Ok(data.len()),
+            Ok(Err(e)) => {
+                warn!("udp send failed: {:?}", e);
+                Err(TransportError::Link)
+            }
+            Err(_) => Err(TransportError::Timeout),
+        }
+    }
+
+    fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> Result<usize, TransportError> {
+        block_on(with_timeout(timeout, self.receive(buffer))).unwrap_or(Ok(0))
+    }
+}
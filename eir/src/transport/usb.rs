@@ -0,0 +1,269 @@
+//! XRCE transport over USB CDC ACM.
+//!
+//! The USB driver runs in embassy tasks, which have to run in an interrupt executor with a higher
+//! priority than the micro-ROS code. The tasks exchange data with `UsbTransport` through statics.
+
+use embassy_executor::Spawner;
+use embassy_rp::{
+    bind_interrupts,
+    usb::{Driver, InterruptHandler},
+};
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex,
+    channel::{self, Channel},
+    pipe::Pipe,
+};
+use embassy_time::{with_timeout, Duration};
+use embassy_usb::{
+    class::cdc_acm::{self, CdcAcmClass, State},
+    driver::EndpointError,
+};
+use portable_atomic::{AtomicU32, Ordering};
+use static_cell::StaticCell;
+
+use super::{block_on, Transport, TransportError, WRITE_TIMEOUT};
+
+bind_interrupts!(struct Irqs {
+    USBCTRL_IRQ => InterruptHandler<embassy_rp::peripherals::USB>;
+});
+
+pub type MyUsbDriver = Driver<'static, embassy_rp::peripherals::USB>;
+pub type MyUsbDevice = embassy_usb::UsbDevice<'static, MyUsbDriver>;
+
+pub fn usb_config() -> embassy_usb::Config<'static> {
+    let mut config = embassy_usb::Config::new(0xc0de, 0xcafe);
+    config.manufacturer = Some("Moonforge");
+    config.product = Some("Eir - robot doctor");
+    config.serial_number = Some("12345678");
+    config.max_power = 100;
+    config.max_packet_size_0 = 64;
+
+    // Required for windows compatibility.
+    // https://developer.nordicsemi.com/nRF_Connect_SDK/doc/1.9.1/kconfig/CONFIG_CDC_ACM_IAD.html#help
+    config.device_class = 0xEF;
+    config.device_sub_class = 0x02;
+    config.device_protocol = 0x01;
+    config.composite_with_iads = true;
+    config
+}
+
+fn usb_builder(
+    usb: embassy_rp::peripherals::USB,
+) -> embassy_usb::Builder<'static, embassy_rp::usb::Driver<'static, embassy_rp::peripherals::USB>> {
+    let config = usb_config();
+    let driver = Driver::new(usb, Irqs);
+    static CONFIG_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+    static BOS_DESCRIPTOR: StaticCell<[u8; 256]> = StaticCell::new();
+    static CONTROL_BUF: StaticCell<[u8; 64]> = StaticCell::new();
+
+    let builder = embassy_usb::Builder::new(
+        driver,
+        config,
+        CONFIG_DESCRIPTOR.init([0; 256]),
+        BOS_DESCRIPTOR.init([0; 256]),
+        &mut [], // no msos descriptors
+        CONTROL_BUF.init([0; 64]),
+    );
+    builder
+}
+
+pub fn init_usb(
+    peri: embassy_rp::peripherals::USB,
+) -> (cdc_acm::CdcAcmClass<'static, MyUsbDriver>, MyUsbDevice) {
+    let mut builder = usb_builder(peri);
+    let class = {
+        static STATE: StaticCell<State> = StaticCell::new();
+        let state = STATE.init(State::new());
+
+        CdcAcmClass::new(&mut builder, state, 64)
+    };
+
+    let usb = builder.build();
+
+    (class, usb)
+}
+
+pub async fn init_usb_transport(peri: embassy_rp::peripherals::USB, spawner: &Spawner) {
+    // TODO: we could check that this runs in interrupt mode
+    let (mut class, usb) = init_usb(peri);
+
+    defmt::unwrap!(spawner.spawn(usb_task(usb)));
+
+    defmt::error!("waiting for usb");
+    class.wait_connection().await;
+    defmt::error!("we have usb");
+
+    let (sender, receiver) = class.split();
+
+    defmt::unwrap!(spawner.spawn(sender_task(
+        SENDER_CHANNEL.receiver(),
+        COMPLETION_CHANNEL.sender(),
+        sender
+    )));
+    defmt::unwrap!(spawner.spawn(receiver_task(&RECEIVER_PIPE, receiver)));
+}
+
+#[embassy_executor::task]
+pub async fn usb_task(mut usb: MyUsbDevice) -> ! {
+    usb.run().await
+}
+
+pub const BUFFER_LEN: usize = 1024;
+pub const QUEUE_LEN: usize = 2;
+
+#[derive(defmt::Format)]
+pub struct Buffer {
+    inner: [u8; BUFFER_LEN],
+    used: usize,
+    /// Identifies the write in the `Completion` reported by `sender_task`
+    sequence: u32,
+    /// Whether this is the final chunk of a write, which ends the USB transfer
+    last: bool,
+}
+
+impl Buffer {}
+
+/// Outcome of sending the buffer with the given sequence number
+pub type Completion = (u32, Result<(), EndpointError>);
+
+pub static SENDER_CHANNEL: Channel<CriticalSectionRawMutex, Buffer, QUEUE_LEN> = Channel::new();
+pub static COMPLETION_CHANNEL: Channel<CriticalSectionRawMutex, Completion, QUEUE_LEN> =
+    Channel::new();
+pub static RECEIVER_PIPE: Pipe<CriticalSectionRawMutex, BUFFER_LEN> = Pipe::new();
+
+#[embassy_executor::task]
+pub async fn sender_task(
+    receiver: channel::Receiver<'static, CriticalSectionRawMutex, Buffer, QUEUE_LEN>,
+    completions: channel::Sender<'static, CriticalSectionRawMutex, Completion, QUEUE_LEN>,
+    mut sender: cdc_acm::Sender<'static, MyUsbDriver>,
+) {
+    loop {
+        let buffer = receiver.receive().await;
+        let result = write_buffer(&mut sender, &buffer).await;
+        if let Err(e) = result {
+            defmt::warn!("failed to send {} bytes: {}", buffer.used, e);
+        }
+        completions.send((buffer.sequence, result)).await;
+    }
+}
+
+/// Splits the buffer into packets of the endpoint's maximum size. A transfer ending with a full
+/// packet has to be terminated by a zero-length packet, otherwise the host keeps waiting for data.
+async fn write_buffer(
+    sender: &mut cdc_acm::Sender<'static, MyUsbDriver>,
+    buffer: &Buffer,
+) -> Result<(), EndpointError> {
+    let max_packet_size = sender.max_packet_size() as usize;
+    let data = &buffer.inner[..buffer.used];
+    for packet in data.chunks(max_packet_size) {
+        sender.write_packet(packet).await?;
+    }
+    if buffer.last && data.len() % max_packet_size == 0 {
+        sender.write_packet(&[]).await?;
+    }
+    Ok(())
+}
+
+#[embassy_executor::task]
+pub async fn receiver_task(
+    pipe: &'static Pipe<CriticalSectionRawMutex, BUFFER_LEN>,
+    mut receiver: cdc_acm::Receiver<'static, MyUsbDriver>,
+) {
+    let mut buffer = [0u8; 64];
+    loop {
+        let received = defmt::unwrap!(receiver.read_packet(&mut buffer[..]).await);
+        pipe.write_all(&buffer[..received]).await;
+        super::notify_received();
+    }
+}
+
+/// Transport handing data to the tasks spawned by `init_usb_transport`
+pub struct UsbTransport {
+    _private: (),
+}
+
+impl UsbTransport {
+    pub const fn new() -> Self {
+        Self { _private: () }
+    }
+}
+
+impl Default for UsbTransport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Transport for UsbTransport {
+    /// USB CDC is a byte stream, the client has to frame the messages
+    const FRAMING: bool = true;
+
+    fn write(&mut self, data: &[u8]) -> Result<usize, TransportError> {
+        defmt::trace!("write requested: {} bytes", data.len());
+
+        let mut written = 0;
+        for chunk in data.chunks(BUFFER_LEN) {
+            let mut buffer = Buffer {
+                inner: [0u8; BUFFER_LEN],
+                used: chunk.len(),
+                sequence: WRITE_SEQUENCE.fetch_add(1, Ordering::Relaxed),
+                last: written + chunk.len() == data.len(),
+            };
+            buffer.inner[..chunk.len()].copy_from_slice(chunk);
+
+            // the transport functions run in thread mode, while `sender_task` progresses in the
+            // interrupt executor, so blocking here is fine. The timeout works outside an
+            // executor as embassy-time uses its generic timer queue, which accepts any waker.
+            match block_on(with_timeout(WRITE_TIMEOUT, send(buffer))) {
+                Ok(Ok(())) => written += chunk.len(),
+                Ok(Err(e)) => {
+                    defmt::warn!("write failed after {} bytes: {}", written, e);
+                    return Err(TransportError::Link);
+                }
+                Err(_) => {
+                    defmt::warn!("write timed out after {} bytes", written);
+                    return Err(TransportError::Timeout);
+                }
+            }
+        }
+
+        Ok(written)
+    }
+
+    fn read(&mut self, buffer: &mut [u8], timeout: Duration) -> Result<usize, TransportError> {
+        defmt::trace!("read requested: {}", buffer.len());
+
+        let mut read = 0;
+        let result = block_on(with_timeout(timeout, async {
+            while read < buffer.len() {
+                read += RECEIVER_PIPE.read(&mut buffer[read..]).await;
+            }
+        }));
+        if result.is_err() {
+            defmt::trace!("timeout while reading");
+        }
+        // the client reads into a buffer smaller than the pipe, ask for another read
+        if !RECEIVER_PIPE.is_empty() {
+            super::notify_received();
+        }
+
+        Ok(read)
+    }
+}
+
+static WRITE_SEQUENCE: AtomicU32 = AtomicU32::new(0);
+
+/// Queues the buffer, waiting for space if `sender_task` is busy, and resolves once it was sent
+async fn send(buffer: Buffer) -> Result<(), EndpointError> {
+    // completions of earlier writes that timed out
+    while COMPLETION_CHANNEL.try_receive().is_ok() {}
+
+    let sequence = buffer.sequence;
+    SENDER_CHANNEL.send(buffer).await;
+    loop {
+        let (completed, result) = COMPLETION_CHANNEL.receive().await;
+        if completed == sequence {
+            return result;
+        }
+    }
+}
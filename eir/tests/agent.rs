@@ -0,0 +1,517 @@
+//! Runs the micro-ROS client against the fake agent of `eir::transport::mock`.
+//! Needs a libmicroros built for the host, see the README.
+
+use std::cell::Cell;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Mutex, OnceLock};
+
+use eir::microros::{
+    self, AgentState, AgentSupervisor, AgentUnavailable, Allocator, Connection, PingPolicy,
+    QosProfile, RclNode, RclTimer, RclcExecutor, RclcSupport, ReadyHandles, Trigger, TypedClient,
+    TypedPublisher, TypedSubscription, MAX_PENDING_CALLS,
+};
+use eir::msg::std_msgs::{plain, Int32};
+use eir::msg::std_srvs::{SetBool, SetBoolRequest};
+use eir::rosidl::RosString;
+use eir::transport::init_rmw_transport;
+use eir::transport::mock::{FakeAgent, MockHandle, MockTransport, ObjectKind};
+use embassy_futures::{join::join, poll_once, select::select};
+use embassy_time::{with_timeout, Duration, Instant, Timer};
+
+/// micro-ROS keeps its session in global state, so the tests take turns
+static MICROROS: Mutex<()> = Mutex::new(());
+
+fn connect() -> MockHandle {
+    let transport = Box::leak(Box::new(MockTransport::with_agent(FakeAgent::new())));
+    let handle = transport.handle();
+    init_rmw_transport(transport);
+    handle
+}
+
+#[test]
+fn waits_for_agent() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let handle = connect();
+
+    embassy_futures::block_on(microros::wait_for_agent(PingPolicy::default())).unwrap();
+
+    assert!(handle.agent(|agent| agent.pings()).unwrap() >= 1);
+}
+
+#[test]
+fn gives_up_waiting_for_agent() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let handle = connect();
+    handle.agent(|agent| agent.set_available(false));
+
+    // pauses 5 ms after the first and 10 ms after the second ping
+    let policy = PingPolicy::new(Duration::from_millis(10), 3)
+        .with_backoff(Duration::from_millis(5), Duration::from_millis(20));
+    let start = Instant::now();
+    let result = embassy_futures::block_on(microros::wait_for_agent(policy));
+
+    assert_eq!(result, Err(AgentUnavailable { attempts: 3 }));
+    assert!(start.elapsed() >= Duration::from_millis(15));
+    assert_eq!(handle.agent(|agent| agent.pings()), Some(0));
+}
+
+#[test]
+fn creates_node() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let handle = connect();
+    let allocator = Allocator::default();
+
+    let support = RclcSupport::new(&allocator).unwrap();
+    let _node = RclNode::new("mock_node", "", &support).unwrap();
+
+    handle
+        .agent(|agent| {
+            assert_eq!(agent.sessions(), 1);
+            assert!(agent
+                .entities()
+                .iter()
+                .any(|entity| entity.kind == ObjectKind::Participant));
+        })
+        .unwrap();
+}
+
+#[test]
+fn publishes() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let handle = connect();
+    let allocator = Allocator::default();
+    let support = RclcSupport::new(&allocator).unwrap();
+    let node = RclNode::new("mock_node", "", &support).unwrap();
+
+    let mut publisher = TypedPublisher::<Int32>::new(&node, "mock_publisher").unwrap();
+    let mut message = Int32::default();
+    message.data = 42;
+    publisher.publish(&message).unwrap();
+
+    let written = handle
+        .agent(|agent| {
+            agent
+                .written_to("rt/mock_publisher")
+                .cloned()
+                .collect::<Vec<_>>()
+        })
+        .unwrap();
+    assert_eq!(written.len(), 1);
+    assert_eq!(written[0].data, 42i32.to_le_bytes());
+}
+
+#[test]
+fn pool_returns_to_baseline() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let _handle = connect();
+    let allocator =
+        Allocator::from_pool(Box::leak(vec![0u8; 64 * 1024].into_boxed_slice())).unwrap();
+    let baseline = allocator.pool_stats().unwrap().used;
+
+    {
+        let support = RclcSupport::new(&allocator).unwrap();
+        let node = RclNode::new("mock_node", "", &support).unwrap();
+        let _publisher = TypedPublisher::<Int32>::new(&node, "mock_publisher").unwrap();
+        assert!(allocator.pool_stats().unwrap().used > baseline);
+    }
+
+    let stats = allocator.pool_stats().unwrap();
+    assert_eq!(stats.used, baseline);
+    assert_eq!(stats.failed_allocations, 0);
+}
+
+static PLAIN: plain::Int32 = plain::Int32 { data: 42 };
+
+#[test]
+fn publishes_plain_message() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let handle = connect();
+    let allocator = Allocator::default();
+    let support = RclcSupport::new(&allocator).unwrap();
+    let node = RclNode::new("mock_node", "", &support).unwrap();
+
+    let mut publisher = TypedPublisher::<plain::Int32>::new(&node, "mock_plain").unwrap();
+    publisher.publish(&PLAIN).unwrap();
+    let on_stack = plain::Int32 { data: 43 };
+    publisher.publish(&on_stack).unwrap();
+
+    let written = handle
+        .agent(|agent| {
+            agent
+                .written_to("rt/mock_plain")
+                .map(|written| written.data.clone())
+                .collect::<Vec<_>>()
+        })
+        .unwrap();
+    assert_eq!(written, [42i32.to_le_bytes(), 43i32.to_le_bytes()]);
+}
+
+#[test]
+fn publishes_with_qos() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let handle = connect();
+    let allocator = Allocator::default();
+    let support = RclcSupport::new(&allocator).unwrap();
+    let node = RclNode::new("mock_node", "", &support).unwrap();
+
+    let mut sensor =
+        TypedPublisher::<Int32>::with_qos(&node, "mock_sensor", QosProfile::sensor_data()).unwrap();
+    let mut state =
+        TypedPublisher::<Int32>::with_qos(&node, "mock_state", QosProfile::default().keep_last(1))
+            .unwrap();
+    sensor.publish(&Int32::default()).unwrap();
+    state.publish(&Int32::default()).unwrap();
+
+    handle
+        .agent(|agent| {
+            let sensor = agent.written_to("rt/mock_sensor").next().unwrap();
+            assert!(!sensor.is_reliable());
+            let state = agent.written_to("rt/mock_state").next().unwrap();
+            assert!(state.is_reliable());
+        })
+        .unwrap();
+}
+
+#[test]
+fn best_effort_uses_best_effort_streams() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let handle = connect();
+    let allocator = Allocator::default();
+    let support = RclcSupport::new(&allocator).unwrap();
+    let node = RclNode::new("mock_node", "", &support).unwrap();
+
+    let mut publisher = TypedPublisher::<Int32>::best_effort(&node, "mock_telemetry").unwrap();
+    publisher.publish(&Int32::default()).unwrap();
+
+    let received = Cell::new(None);
+    let mut best_effort =
+        TypedSubscription::<Int32>::best_effort(&node, "mock_best_effort").unwrap();
+    let mut reliable = TypedSubscription::<Int32>::new(&node, "mock_reliable").unwrap();
+    let mut executor = RclcExecutor::new(&support, 2, &allocator).unwrap();
+    executor
+        .add_typed_subscription(&mut best_effort, |msg: &Int32| received.set(Some(msg.data)))
+        .unwrap();
+    executor
+        .add_typed_subscription(&mut reliable, |_: &Int32| {})
+        .unwrap();
+    // lets the client flush the data requests of the readers
+    executor.spin().unwrap();
+
+    handle
+        .agent(|agent| {
+            let writer = agent
+                .entity(ObjectKind::DataWriter, "rt/mock_telemetry")
+                .unwrap();
+            let written = agent.written_to("rt/mock_telemetry").next().unwrap();
+            assert_eq!(written.object_id, writer.object_id);
+            assert!(!written.is_reliable());
+            assert_eq!(agent.reads_reliable("rt/mock_best_effort"), Some(false));
+            assert_eq!(agent.reads_reliable("rt/mock_reliable"), Some(true));
+            assert!(agent.deliver("rt/mock_best_effort", &5i32.to_le_bytes()));
+        })
+        .unwrap();
+
+    for _ in 0..10 {
+        executor.spin().unwrap();
+        if received.get().is_some() {
+            break;
+        }
+    }
+    assert_eq!(received.get(), Some(5));
+}
+
+#[test]
+fn delivers_to_subscription() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let handle = connect();
+    let allocator = Allocator::default();
+    let support = RclcSupport::new(&allocator).unwrap();
+    let node = RclNode::new("mock_node", "", &support).unwrap();
+
+    let received = Cell::new(None);
+    let mut subscription = TypedSubscription::<Int32>::new(&node, "mock_subscriber").unwrap();
+    let mut executor = RclcExecutor::new(&support, 1, &allocator).unwrap();
+    executor
+        .add_typed_subscription(&mut subscription, |msg: &Int32| {
+            received.set(Some(msg.data))
+        })
+        .unwrap();
+
+    // lets the client flush the data request of the reader
+    executor.spin().unwrap();
+    let delivered = handle
+        .agent(|agent| agent.deliver("rt/mock_subscriber", &7i32.to_le_bytes()))
+        .unwrap();
+    assert!(delivered);
+
+    for _ in 0..10 {
+        executor.spin().unwrap();
+        if received.get().is_some() {
+            break;
+        }
+    }
+    assert_eq!(received.get(), Some(7));
+}
+
+#[test]
+fn dropped_calls_release_their_slots() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let _handle = connect();
+    let allocator = Allocator::default();
+    let support = RclcSupport::new(&allocator).unwrap();
+    let node = RclNode::new("mock_node", "", &support).unwrap();
+    let client = TypedClient::<SetBool>::new(&node, "mock_service").unwrap();
+    let request = SetBoolRequest::default();
+
+    // the requests are sent right away, the futures are never polled
+    for _ in 0..=MAX_PENDING_CALLS {
+        drop(client.call(&request));
+    }
+
+    assert!(poll_once(client.call(&request)).is_pending());
+}
+
+/// CDR of a `SetBool` response, without encapsulation like the rest of the mock
+fn set_bool_response(success: bool, message: &str) -> Vec<u8> {
+    let mut response = vec![success as u8, 0, 0, 0];
+    response.extend_from_slice(&(message.len() as u32 + 1).to_le_bytes());
+    response.extend_from_slice(message.as_bytes());
+    response.push(0);
+    response
+}
+
+#[test]
+fn matches_responses_to_calls() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let handle = connect();
+    let allocator = Allocator::default();
+    let support = RclcSupport::new(&allocator).unwrap();
+    let node = RclNode::new("mock_node", "", &support).unwrap();
+    let client = TypedClient::<SetBool>::new(&node, "mock_service").unwrap();
+    let mut executor = RclcExecutor::new(&support, 1, &allocator).unwrap();
+    executor.add_typed_client(&client).unwrap();
+
+    let mut request = SetBoolRequest::default();
+    request.data = true;
+    let first = client.call(&request);
+    request.data = false;
+    let second = client.call(&request);
+
+    // lets the client flush the requests and the data request of the requester
+    executor.spin().unwrap();
+    let requests = handle
+        .agent(|agent| agent.requests().cloned().collect::<Vec<_>>())
+        .unwrap();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].data, [1]);
+    assert_eq!(requests[1].data, [0]);
+
+    // answers the second call first
+    handle
+        .agent(|agent| {
+            assert!(agent.reply(&requests[1], &set_bool_response(false, "second")));
+            assert!(agent.reply(&requests[0], &set_bool_response(true, "first")));
+        })
+        .unwrap();
+    for _ in 0..10 {
+        executor.spin().unwrap();
+    }
+
+    let (first, second) =
+        embassy_futures::block_on(with_timeout(Duration::from_secs(1), join(first, second)))
+            .unwrap();
+    let (first, second) = (first.unwrap(), second.unwrap());
+    assert!(first.success);
+    assert_eq!(first.message.as_str(), Ok("first"));
+    assert!(!second.success);
+    assert_eq!(second.message.as_str(), Ok("second"));
+}
+
+#[test]
+fn unanswered_call_times_out() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let _handle = connect();
+    let allocator = Allocator::default();
+    let support = RclcSupport::new(&allocator).unwrap();
+    let node = RclNode::new("mock_node", "", &support).unwrap();
+    let mut client = TypedClient::<SetBool>::new(&node, "mock_service").unwrap();
+    client.set_timeout(Duration::from_millis(20));
+
+    let start = Instant::now();
+    let result = embassy_futures::block_on(client.call(&SetBoolRequest::default()));
+
+    assert!(matches!(result, Err(microros::Error::Timeout)));
+    assert!(start.elapsed() >= Duration::from_millis(20));
+}
+
+static RECONNECTING: OnceLock<MockHandle> = OnceLock::new();
+static SESSIONS: AtomicUsize = AtomicUsize::new(0);
+static SUPERVISOR: AgentSupervisor = AgentSupervisor::new(Duration::from_millis(10));
+
+/// Loses the agent in the first session, returns right away in the second one
+async fn reconnecting_session(connection: &Connection<'_>) -> Result<(), microros::Error> {
+    let _node = RclNode::new("mock_node", "", connection.support())?;
+    let mut executor = RclcExecutor::new(connection.support(), 1, connection.allocator())?;
+    if SESSIONS.fetch_add(1, Ordering::Relaxed) > 0 {
+        return Ok(());
+    }
+
+    let handle = RECONNECTING.get().unwrap();
+    handle.agent(|agent| agent.set_available(false));
+    connection.spin(&mut executor).await;
+    assert_eq!(SUPERVISOR.state(), AgentState::AgentConnected);
+    handle.agent(|agent| agent.set_available(true));
+    Ok(())
+}
+
+#[test]
+fn reconnects() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let handle = RECONNECTING.get_or_init(connect);
+    let allocator = Allocator::default();
+
+    // the supervisor waits with embassy timers, which embassy_futures::block_on supports as
+    // embassy-time uses its generic timer queue
+    let result = embassy_futures::block_on(with_timeout(
+        Duration::from_secs(5),
+        SUPERVISOR.run(&allocator, reconnecting_session),
+    ));
+
+    assert!(result.is_ok());
+    assert_eq!(SESSIONS.load(Ordering::Relaxed), 2);
+    assert_eq!(handle.agent(|agent| agent.sessions()), Some(2));
+}
+
+#[test]
+fn runs_executor_until_data_arrives() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let handle = connect();
+    let allocator = Allocator::default();
+    let support = RclcSupport::new(&allocator).unwrap();
+    let node = RclNode::new("mock_node", "", &support).unwrap();
+
+    let received = Cell::new(None);
+    let mut subscription = TypedSubscription::<Int32>::new(&node, "mock_subscriber").unwrap();
+    let mut executor = RclcExecutor::new(&support, 1, &allocator).unwrap();
+    executor
+        .add_typed_subscription(&mut subscription, |msg: &Int32| {
+            received.set(Some(msg.data))
+        })
+        .unwrap();
+
+    let receive = async {
+        while !handle
+            .agent(|agent| agent.deliver("rt/mock_subscriber", &9i32.to_le_bytes()))
+            .unwrap()
+        {
+            Timer::after_millis(1).await;
+        }
+        while received.get().is_none() {
+            Timer::after_millis(1).await;
+        }
+    };
+    let result = embassy_futures::block_on(with_timeout(
+        Duration::from_secs(5),
+        select(executor.run(), receive),
+    ));
+
+    assert!(result.is_ok());
+    assert_eq!(received.get(), Some(9));
+}
+
+#[test]
+fn trigger_holds_back_processing() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let handle = connect();
+    let allocator = Allocator::default();
+    let support = RclcSupport::new(&allocator).unwrap();
+    let node = RclNode::new("mock_node", "", &support).unwrap();
+
+    let received = Cell::new(None);
+    let mut subscription = TypedSubscription::<Int32>::new(&node, "mock_subscriber").unwrap();
+    let mut executor = RclcExecutor::new(&support, 2, &allocator).unwrap();
+    executor
+        .add_typed_subscription(&mut subscription, |msg: &Int32| {
+            received.set(Some(msg.data))
+        })
+        .unwrap();
+    executor
+        .set_trigger_fn(|handles: &ReadyHandles<'_>| handles.len() > 1)
+        .unwrap();
+
+    executor.spin_some(Duration::from_millis(10)).unwrap();
+    handle
+        .agent(|agent| agent.deliver("rt/mock_subscriber", &3i32.to_le_bytes()))
+        .unwrap();
+    executor
+        .spin_until(Instant::now() + Duration::from_millis(50))
+        .unwrap();
+    assert_eq!(received.get(), None);
+
+    executor.set_trigger(Trigger::One(0)).unwrap();
+    executor
+        .spin_until(Instant::now() + Duration::from_millis(50))
+        .unwrap();
+    assert_eq!(received.get(), Some(3));
+}
+
+#[test]
+fn replaced_triggers_are_freed() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let _handle = connect();
+    let allocator =
+        Allocator::from_pool(Box::leak(vec![0u8; 64 * 1024].into_boxed_slice())).unwrap();
+    let support = RclcSupport::new(&allocator).unwrap();
+    let mut executor = RclcExecutor::new(&support, 1, &allocator).unwrap();
+
+    executor
+        .set_trigger_fn(|handles: &ReadyHandles<'_>| handles.is_ready(0))
+        .unwrap();
+    let used = allocator.pool_stats().unwrap().used;
+    for minimum in 0..10 {
+        executor
+            .set_trigger_fn(move |handles: &ReadyHandles<'_>| handles.len() > minimum)
+            .unwrap();
+    }
+    assert_eq!(allocator.pool_stats().unwrap().used, used);
+
+    executor.set_trigger(Trigger::Any).unwrap();
+    assert!(allocator.pool_stats().unwrap().used < used);
+}
+
+#[test]
+fn timer_calls_closure_until_canceled() {
+    let _guard = MICROROS.lock().unwrap_or_else(|e| e.into_inner());
+    let _handle = connect();
+    let allocator = Allocator::default();
+    let support = RclcSupport::new(&allocator).unwrap();
+
+    let calls = Cell::new(0);
+    let timer = RclTimer::new(&support, Duration::from_millis(10), |_| {
+        calls.set(calls.get() + 1)
+    })
+    .unwrap();
+    let mut executor = RclcExecutor::new(&support, 1, &allocator).unwrap();
+    executor.add_timer(&timer).unwrap();
+
+    executor
+        .spin_until(Instant::now() + Duration::from_millis(100))
+        .unwrap();
+    assert!(calls.get() > 0);
+
+    timer.cancel().unwrap();
+    assert!(timer.is_canceled().unwrap());
+    calls.set(0);
+    executor
+        .spin_until(Instant::now() + Duration::from_millis(50))
+        .unwrap();
+    assert_eq!(calls.get(), 0);
+
+    let previous = timer.change_period(Duration::from_millis(5)).unwrap();
+    assert_eq!(previous, Duration::from_millis(10));
+    timer.reset().unwrap();
+    executor
+        .spin_until(Instant::now() + Duration::from_millis(50))
+        .unwrap();
+    assert!(calls.get() > 0);
+}
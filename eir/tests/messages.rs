@@ -0,0 +1,133 @@
+//! Converts the owned messages to and from the rosidl structures of the wrappers.
+//! Needs a libmicroros built for the host, see the README.
+
+use eir::msg::sensor_msgs::{self, BatteryState, JointState};
+use eir::msg::{ConversionError, OwnedMessage, SEQUENCE_CAPACITY, STRING_CAPACITY};
+use eir::rosidl::{RosString, Sequence};
+
+fn battery(cells: &[f32]) -> sensor_msgs::owned::BatteryState {
+    let mut battery = sensor_msgs::owned::BatteryState {
+        voltage: 11.1,
+        present: true,
+        cell_voltage: heapless::Vec::from_slice(cells).unwrap(),
+        location: "left".try_into().unwrap(),
+        ..Default::default()
+    };
+    battery.header.stamp.sec = 42;
+    battery.header.frame_id = "battery".try_into().unwrap();
+    battery
+}
+
+#[test]
+fn round_trips_battery_state() {
+    let owned = battery(&[3.7, 3.8, 3.6]);
+
+    let wrapper = BatteryState::try_from(&owned).unwrap();
+    assert_eq!(wrapper.cell_voltage.as_slice(), [3.7, 3.8, 3.6]);
+    assert_eq!(wrapper.header.frame_id.as_str(), Ok("battery"));
+    assert_eq!(wrapper.location.as_str(), Ok("left"));
+
+    assert_eq!(
+        sensor_msgs::owned::BatteryState::try_from(&wrapper),
+        Ok(owned)
+    );
+}
+
+#[test]
+fn resizes_sequences_between_writes() {
+    let mut wrapper = BatteryState::default();
+
+    for cells in [&[3.7, 3.8, 3.6][..], &[4.1], &[], &[3.9, 3.9, 4.0, 4.2]] {
+        let owned = battery(cells);
+        owned.write_raw(&mut wrapper).unwrap();
+        assert_eq!(
+            sensor_msgs::owned::BatteryState::from_raw(&wrapper),
+            Ok(owned)
+        );
+    }
+}
+
+#[test]
+fn resizes_string_sequences_between_writes() {
+    let mut wrapper = JointState::default();
+
+    for names in [&["shoulder", "elbow", "wrist"][..], &["gripper"]] {
+        let mut owned = sensor_msgs::owned::JointState::default();
+        for name in names {
+            owned.name.push((*name).try_into().unwrap()).unwrap();
+            owned.position.push(0.5).unwrap();
+        }
+        owned.write_raw(&mut wrapper).unwrap();
+        assert_eq!(
+            sensor_msgs::owned::JointState::from_raw(&wrapper),
+            Ok(owned)
+        );
+    }
+}
+
+#[test]
+fn rejects_oversized_sequences() {
+    let mut wrapper = BatteryState::default();
+    wrapper.cell_voltage.resize(SEQUENCE_CAPACITY + 1).unwrap();
+
+    assert_eq!(
+        sensor_msgs::owned::BatteryState::try_from(&wrapper),
+        Err(ConversionError::CapacityExceeded)
+    );
+}
+
+#[test]
+fn rejects_oversized_strings() {
+    let mut wrapper = BatteryState::default();
+    let long = "x".repeat(STRING_CAPACITY + 1);
+    wrapper.header.frame_id.set(&long).unwrap();
+
+    assert_eq!(
+        sensor_msgs::owned::BatteryState::try_from(&wrapper),
+        Err(ConversionError::CapacityExceeded)
+    );
+}
+
+#[test]
+fn drops_shrunk_string_sequences() {
+    let mut wrapper = JointState::default();
+    wrapper.name.resize(3).unwrap();
+    for (name, value) in wrapper.name.iter_mut().zip(["shoulder", "elbow", "wrist"]) {
+        name.set(value).unwrap();
+    }
+
+    wrapper.name.resize(1).unwrap();
+    assert_eq!(wrapper.name.as_slice()[0].as_str(), Ok("shoulder"));
+    // rosidl finalizes the removed elements again when the message is dropped
+    drop(wrapper);
+}
+
+#[test]
+fn regrows_shrunk_string_sequences() {
+    let mut wrapper = JointState::default();
+    wrapper.name.resize(3).unwrap();
+    wrapper.name.as_mut_slice()[2].set("wrist").unwrap();
+    wrapper.name.resize(1).unwrap();
+    wrapper.name.resize(4).unwrap();
+
+    assert_eq!(wrapper.name.len(), 4);
+    assert!(wrapper
+        .name
+        .iter()
+        .skip(1)
+        .all(|name| name.as_str() == Ok("")));
+    wrapper.name.as_mut_slice()[3].set("gripper").unwrap();
+    wrapper.name.resize(0).unwrap();
+    drop(wrapper);
+}
+
+#[test]
+fn drops_shrunk_strings() {
+    let mut wrapper = BatteryState::default();
+    wrapper.location.set("left front cell").unwrap();
+    wrapper.location.set("left").unwrap();
+    wrapper.location.set("").unwrap();
+
+    assert_eq!(wrapper.location.as_str(), Ok(""));
+    drop(wrapper);
+}
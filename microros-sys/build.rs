@@ -2,36 +2,60 @@ use std::env;
 use std::path::PathBuf;
 
 fn main() {
-    let lib_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
-        .join("../micro_ros_raspberrypi_pico_sdk/libmicroros/");
+    // the host build links a libmicroros built for Linux, e.g. with the micro_ros_setup
+    // `generate_lib` script, the embedded build the one shipped with the pico SDK
+    let embedded = env::var("CARGO_CFG_TARGET_OS").as_deref() == Ok("none");
+    println!("cargo:rerun-if-env-changed=MICROROS_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=MICROROS_INCLUDE_DIR");
+
+    let lib_dir = match env::var_os("MICROROS_LIB_DIR") {
+        Some(dir) => PathBuf::from(dir),
+        None if embedded => PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap())
+            .join("../micro_ros_raspberrypi_pico_sdk/libmicroros/"),
+        None => panic!("set MICROROS_LIB_DIR to the directory of a libmicroros built for the host"),
+    };
+    let include_dir = env::var_os("MICROROS_INCLUDE_DIR")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| lib_dir.join("include"));
 
     println!("cargo:rustc-link-search={}", lib_dir.to_str().unwrap());
-    println!(
-        "cargo:rustc-link-search={}",
-        "/usr/lib/arm-none-eabi/lib/thumb/v6-m/nofp/"
-    );
-    println!(
-        "cargo:rustc-link-search={}",
-        "/usr/lib/gcc/arm-none-eabi/10.3.1/thumb/v6-m/nofp/"
-    );
     println!("cargo:rustc-link-lib=microros");
-    println!("cargo:rustc-link-lib=nosys");
-    println!("cargo:rustc-link-lib=c");
-    println!("cargo:rustc-link-lib=m");
-    println!("cargo:rustc-link-lib=g");
-    println!("cargo:rustc-link-lib=stdc++");
-    println!("cargo:rustc-link-lib=gcc");
-
-    let bindings = bindgen::Builder::default()
+
+    let mut builder = bindgen::Builder::default()
         .header("wrapper.h")
         .use_core()
-        .clang_arg(format!("-I{}", lib_dir.join("include").to_str().unwrap()))
-        .clang_arg(format!("-I{}", "/usr/lib/arm-none-eabi/include/"))
-        .clang_arg(format!("-I{}", "/usr/lib/gcc/arm-none-eabi/10.3.1/include/"))
-        .generate()
-        .expect("generation failed");
+        .clang_arg(format!("-I{}", include_dir.to_str().unwrap()));
+
+    if embedded {
+        println!(
+            "cargo:rustc-link-search={}",
+            "/usr/lib/arm-none-eabi/lib/thumb/v6-m/nofp/"
+        );
+        println!(
+            "cargo:rustc-link-search={}",
+            "/usr/lib/gcc/arm-none-eabi/10.3.1/thumb/v6-m/nofp/"
+        );
+        println!("cargo:rustc-link-lib=nosys");
+        println!("cargo:rustc-link-lib=c");
+        println!("cargo:rustc-link-lib=m");
+        println!("cargo:rustc-link-lib=g");
+        println!("cargo:rustc-link-lib=stdc++");
+        println!("cargo:rustc-link-lib=gcc");
+
+        builder = builder
+            .clang_arg(format!("-I{}", "/usr/lib/arm-none-eabi/include/"))
+            .clang_arg(format!("-I{}", "/usr/lib/gcc/arm-none-eabi/10.3.1/include/"));
+    }
+
+    let bindings = builder.generate().expect("generation failed");
     let out_path = PathBuf::from(env::var("OUT_DIR").unwrap());
     bindings
         .write_to_file(out_path.join("bindings.rs"))
         .expect("writing failed");
+
+    // lets the build script of `eir` generate message wrappers from the bindings
+    println!(
+        "cargo:bindings={}",
+        out_path.join("bindings.rs").to_str().unwrap()
+    );
 }
@@ -5,6 +5,8 @@
 #include <rclc/executor.h>
 #include <rmw_microros/rmw_microros.h>
 #include <uxr/client/profile/transport/custom/custom_transport.h>
+#include <rosidl_runtime_c/string_functions.h>
+#include <rosidl_runtime_c/primitives_sequence_functions.h>
 
 
 #include <action_msgs/msg/goal_info.h>
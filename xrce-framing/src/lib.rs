@@ -0,0 +1,372 @@
+//! Framing used by Micro XRCE-DDS on byte streams such as serial links.
+//!
+//! A frame starts with `BEGIN_FLAG`, followed by the source and destination addresses,
+//! the little endian payload length, the payload and its little endian CRC-16. Everything after
+//! the begin flag is byte-stuffed: `BEGIN_FLAG` and `ESC_FLAG` are sent as `ESC_FLAG` followed by
+//! the byte XORed with `XOR_FLAG`. This is the format the micro-ROS agent expects in serial mode.
+//!
+//! The crate is hardware independent, so it can be tested on the host with `cargo test`.
+
+#![no_std]
+
+pub const BEGIN_FLAG: u8 = 0x7E;
+pub const ESC_FLAG: u8 = 0x7D;
+pub const XOR_FLAG: u8 = 0x20;
+
+/// Address used by both the client and the agent unless configured otherwise
+pub const DEFAULT_ADDRESS: u8 = 0;
+
+/// Size of a frame carrying `len` bytes of payload in the worst case, when every byte is escaped
+pub const fn max_frame_len(len: usize) -> usize {
+    1 + 2 * (4 + len + 2)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    /// The payload length does not fit into the 16 bit length field
+    PayloadTooLarge,
+    /// The output buffer cannot hold the frame
+    BufferTooSmall,
+}
+
+/// CRC-16/ARC (reflected polynomial 0x8005, zero initial value), as computed by Micro XRCE-DDS
+pub fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0, |crc, &byte| crc16_update(crc, byte))
+}
+
+pub fn crc16_update(crc: u16, byte: u8) -> u16 {
+    (crc >> 8) ^ CRC16_TABLE[((crc ^ byte as u16) & 0xFF) as usize]
+}
+
+const CRC16_TABLE: [u16; 256] = crc16_table();
+
+const fn crc16_table() -> [u16; 256] {
+    let mut table = [0u16; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u16;
+        let mut bit = 0;
+        while bit < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xA001
+            } else {
+                crc >> 1
+            };
+            bit += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+/// Writes `payload` framed into `out` and returns the length of the frame
+pub fn encode(payload: &[u8], source: u8, destination: u8, out: &mut [u8]) -> Result<usize, Error> {
+    let len = u16::try_from(payload.len()).map_err(|_| Error::PayloadTooLarge)?;
+    let crc = crc16(payload);
+
+    let mut writer = Writer { out, position: 0 };
+    writer.push(BEGIN_FLAG)?;
+    let [len_low, len_high] = len.to_le_bytes();
+    for &byte in [source, destination, len_low, len_high].iter() {
+        writer.push_stuffed(byte)?;
+    }
+    for &byte in payload {
+        writer.push_stuffed(byte)?;
+    }
+    for byte in crc.to_le_bytes() {
+        writer.push_stuffed(byte)?;
+    }
+
+    Ok(writer.position)
+}
+
+struct Writer<'a> {
+    out: &'a mut [u8],
+    position: usize,
+}
+
+impl Writer<'_> {
+    fn push(&mut self, byte: u8) -> Result<(), Error> {
+        let slot = self
+            .out
+            .get_mut(self.position)
+            .ok_or(Error::BufferTooSmall)?;
+        *slot = byte;
+        self.position += 1;
+        Ok(())
+    }
+
+    fn push_stuffed(&mut self, byte: u8) -> Result<(), Error> {
+        if byte == BEGIN_FLAG || byte == ESC_FLAG {
+            self.push(ESC_FLAG)?;
+            self.push(byte ^ XOR_FLAG)
+        } else {
+            self.push(byte)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Waiting for a begin flag
+    Idle,
+    Source,
+    Destination,
+    LengthLow,
+    LengthHigh,
+    Payload,
+    CrcLow,
+    CrcHigh,
+}
+
+/// Reassembles frames from a byte stream, holding payloads of up to `N` bytes.
+///
+/// Frames with a wrong address, a bad CRC or a payload longer than `N` are dropped. A begin flag
+/// always starts a new frame, so the decoder resynchronizes after garbage or truncated frames.
+pub struct Decoder<const N: usize> {
+    /// Address the frames have to come from
+    remote: u8,
+    /// Address the frames have to be sent to
+    local: u8,
+    state: State,
+    escaped: bool,
+    len: usize,
+    received: usize,
+    crc: u16,
+    frame_crc: u16,
+    payload: [u8; N],
+}
+
+impl<const N: usize> Decoder<N> {
+    pub const fn new(local: u8, remote: u8) -> Self {
+        Self {
+            remote,
+            local,
+            state: State::Idle,
+            escaped: false,
+            len: 0,
+            received: 0,
+            crc: 0,
+            frame_crc: 0,
+            payload: [0; N],
+        }
+    }
+
+    /// Forgets the frame being received
+    pub fn reset(&mut self) {
+        self.state = State::Idle;
+        self.escaped = false;
+    }
+
+    /// Feeds the next byte of the stream, returns the payload once a valid frame is complete
+    pub fn push(&mut self, byte: u8) -> Option<&[u8]> {
+        if byte == BEGIN_FLAG {
+            self.state = State::Source;
+            self.escaped = false;
+            return None;
+        }
+        if self.state == State::Idle {
+            return None;
+        }
+        if byte == ESC_FLAG {
+            self.escaped = true;
+            return None;
+        }
+
+        let byte = if self.escaped {
+            self.escaped = false;
+            byte ^ XOR_FLAG
+        } else {
+            byte
+        };
+
+        match self.state {
+            State::Idle => {}
+            State::Source => {
+                self.state = if byte == self.remote {
+                    State::Destination
+                } else {
+                    State::Idle
+                };
+            }
+            State::Destination => {
+                self.state = if byte == self.local {
+                    State::LengthLow
+                } else {
+                    State::Idle
+                };
+            }
+            State::LengthLow => {
+                self.len = byte as usize;
+                self.state = State::LengthHigh;
+            }
+            State::LengthHigh => {
+                self.len |= (byte as usize) << 8;
+                self.received = 0;
+                self.crc = 0;
+                self.state = if self.len > N {
+                    State::Idle
+                } else if self.len == 0 {
+                    State::CrcLow
+                } else {
+                    State::Payload
+                };
+            }
+            State::Payload => {
+                self.payload[self.received] = byte;
+                self.received += 1;
+                self.crc = crc16_update(self.crc, byte);
+                if self.received == self.len {
+                    self.state = State::CrcLow;
+                }
+            }
+            State::CrcLow => {
+                self.frame_crc = byte as u16;
+                self.state = State::CrcHigh;
+            }
+            State::CrcHigh => {
+                self.frame_crc |= (byte as u16) << 8;
+                self.state = State::Idle;
+                if self.frame_crc == self.crc {
+                    return Some(&self.payload[..self.len]);
+                }
+            }
+        }
+
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MTU: usize = 64;
+
+    fn decode_all<const N: usize>(
+        decoder: &mut Decoder<N>,
+        stream: &[u8],
+        out: &mut [u8],
+    ) -> Option<usize> {
+        let mut found = None;
+        for &byte in stream {
+            if let Some(payload) = decoder.push(byte) {
+                out[..payload.len()].copy_from_slice(payload);
+                found = Some(payload.len());
+            }
+        }
+        found
+    }
+
+    #[test]
+    fn crc_matches_check_value() {
+        assert_eq!(crc16(b"123456789"), 0xBB3D);
+        assert_eq!(crc16(&[]), 0);
+    }
+
+    #[test]
+    fn encodes_header_payload_and_crc() {
+        let mut frame = [0u8; max_frame_len(2)];
+        let len = encode(&[0x01, 0x02], 0, 0, &mut frame).unwrap();
+
+        let [crc_low, crc_high] = crc16(&[0x01, 0x02]).to_le_bytes();
+        assert_eq!(
+            &frame[..len],
+            &[BEGIN_FLAG, 0, 0, 2, 0, 0x01, 0x02, crc_low, crc_high]
+        );
+    }
+
+    #[test]
+    fn escapes_flags() {
+        let mut frame = [0u8; max_frame_len(2)];
+        let len = encode(&[BEGIN_FLAG, ESC_FLAG], 0, 0, &mut frame).unwrap();
+
+        assert_eq!(&frame[5..9], &[ESC_FLAG, 0x5E, ESC_FLAG, 0x5D]);
+        assert_eq!(
+            frame[1..len].iter().filter(|&&b| b == BEGIN_FLAG).count(),
+            0
+        );
+    }
+
+    #[test]
+    fn round_trips_every_byte_value() {
+        let payload: [u8; 256] = core::array::from_fn(|i| i as u8);
+        let mut frame = [0u8; max_frame_len(256)];
+        let len = encode(&payload, 1, 2, &mut frame).unwrap();
+
+        let mut decoder = Decoder::<256>::new(2, 1);
+        let mut out = [0u8; 256];
+        assert_eq!(decode_all(&mut decoder, &frame[..len], &mut out), Some(256));
+        assert_eq!(out, payload);
+    }
+
+    #[test]
+    fn round_trips_empty_payload() {
+        let mut frame = [0u8; max_frame_len(0)];
+        let len = encode(&[], 0, 0, &mut frame).unwrap();
+
+        let mut decoder = Decoder::<MTU>::new(0, 0);
+        assert_eq!(decode_all(&mut decoder, &frame[..len], &mut []), Some(0));
+    }
+
+    #[test]
+    fn rejects_small_buffer() {
+        let mut frame = [0u8; 8];
+        assert_eq!(
+            encode(&[0u8; 4], 0, 0, &mut frame),
+            Err(Error::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn drops_corrupted_frame() {
+        let mut frame = [0u8; max_frame_len(3)];
+        let len = encode(&[1, 2, 3], 0, 0, &mut frame).unwrap();
+        frame[6] ^= 0x01;
+
+        let mut decoder = Decoder::<MTU>::new(0, 0);
+        assert_eq!(decode_all(&mut decoder, &frame[..len], &mut [0; MTU]), None);
+    }
+
+    #[test]
+    fn drops_frames_for_other_addresses() {
+        let mut frame = [0u8; max_frame_len(1)];
+        let len = encode(&[7], 0, 5, &mut frame).unwrap();
+
+        let mut decoder = Decoder::<MTU>::new(0, 0);
+        assert_eq!(decode_all(&mut decoder, &frame[..len], &mut [0; MTU]), None);
+    }
+
+    #[test]
+    fn drops_payloads_longer_than_capacity() {
+        let mut frame = [0u8; max_frame_len(MTU + 1)];
+        let len = encode(&[0u8; MTU + 1], 0, 0, &mut frame).unwrap();
+
+        let mut decoder = Decoder::<MTU>::new(0, 0);
+        assert_eq!(decode_all(&mut decoder, &frame[..len], &mut [0; MTU]), None);
+    }
+
+    #[test]
+    fn resynchronizes_after_garbage_and_truncated_frames() {
+        let mut first = [0u8; max_frame_len(4)];
+        let first_len = encode(&[9, 9, 9, 9], 0, 0, &mut first).unwrap();
+        let mut second = [0u8; max_frame_len(3)];
+        let second_len = encode(&[1, 2, 3], 0, 0, &mut second).unwrap();
+
+        let mut decoder = Decoder::<MTU>::new(0, 0);
+        let mut out = [0u8; MTU];
+        let garbage = [0x00, 0x13, ESC_FLAG, 0xFF];
+        assert_eq!(decode_all(&mut decoder, &garbage, &mut out), None);
+        // the first frame is cut off in the middle of its payload
+        assert_eq!(
+            decode_all(&mut decoder, &first[..first_len - 4], &mut out),
+            None
+        );
+        assert_eq!(
+            decode_all(&mut decoder, &second[..second_len], &mut out),
+            Some(3)
+        );
+        assert_eq!(&out[..3], &[1, 2, 3]);
+    }
+}